@@ -1,42 +1,115 @@
-use std::borrow::ToOwned;
+#![allow(clippy::enum_variant_names)]
+
+use std::borrow::{Cow, ToOwned};
 use std::clone::Clone;
-use std::fs::{create_dir_all, File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, create_dir_all, File, OpenOptions};
+use std::io::{BufRead, IsTerminal, Read, Seek, SeekFrom, Write};
 use std::mem::size_of;
+use std::ops::Range;
 use std::os::unix::fs::OpenOptionsExt;
-use std::path::Path;
-use std::rc::Rc;
-use std::time::Instant;
-use std::{io, ptr};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+use std::{env, fmt, io};
 
 use scan_fmt::scan_fmt;
 
+mod node;
+mod row;
+
+use crate::node::{
+    initialize_internal_node, initialize_leaf_node, internal_node_child, internal_node_child_rows,
+    internal_node_find_child, internal_node_insert, internal_node_key, internal_node_num_keys,
+    internal_node_right_child, internal_node_rows_range, is_node_root, leaf_node_cell_range,
+    leaf_node_delete, leaf_node_find, leaf_node_insert, leaf_node_key, leaf_node_num_cells,
+    leaf_node_value, leaf_node_value_mut, node_parent, node_type, set_internal_node_child,
+    set_internal_node_child_rows, set_internal_node_right_child, set_leaf_node_key,
+    set_leaf_node_num_cells, set_node_parent, set_node_root, NodeType,
+};
+use crate::row::{
+    deserialize_raw_row, deserialize_row, serialize_raw_row, serialize_row, unhex, RawRow, Row,
+};
+
 use crate::Error::{ExecuteError, PrepareError, PrepareStringTooLong, TableFull};
-use crate::ExecuteResult::{ExecuteSuccess, ExecuteTableFull};
+use crate::ExecuteResult::ExecuteTableFull;
+
+/// `println!` for the session's output writer.
+macro_rules! outln {
+    ($out:expr, $($arg:tt)*) => {
+        writeln!($out, $($arg)*).expect("failed to write output")
+    };
+}
+
+/// Writes a diagnostic line to stderr when `$level` is enabled.
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        if log_enabled($level) {
+            write_log($level, format_args!($($arg)*));
+        }
+    };
+}
 
-const ID_SIZE: usize = size_of::<i32>();
+const ID_SIZE: usize = size_of::<u32>();
 const USERNAME_SIZE: usize = 32;
 const EMAIL_SIZE: usize = 255;
 const ID_OFFSET: usize = 0;
 const USERNAME_OFFSET: usize = ID_OFFSET + ID_SIZE;
 const EMAIL_OFFSET: usize = USERNAME_OFFSET + USERNAME_SIZE;
 const ROW_SIZE: usize = ID_SIZE + USERNAME_SIZE + EMAIL_SIZE;
+// Text fields are null-terminated, so the last byte of each field is always 0.
+const USERNAME_MAX_LENGTH: usize = USERNAME_SIZE - 1;
+const EMAIL_MAX_LENGTH: usize = EMAIL_SIZE - 1;
+
+// Product Row Layout
+const PRODUCT_ID_SIZE: usize = size_of::<i32>();
+const PRODUCT_NAME_SIZE: usize = 32;
+const PRODUCT_PRICE_SIZE: usize = size_of::<f64>();
+const PRODUCT_ID_OFFSET: usize = 0;
+const PRODUCT_NAME_OFFSET: usize = PRODUCT_ID_OFFSET + PRODUCT_ID_SIZE;
+const PRODUCT_PRICE_OFFSET: usize = PRODUCT_NAME_OFFSET + PRODUCT_NAME_SIZE;
+const PRODUCT_ROW_SIZE: usize = PRODUCT_ID_SIZE + PRODUCT_NAME_SIZE + PRODUCT_PRICE_SIZE;
+const PRODUCT_NAME_MAX_LENGTH: usize = PRODUCT_NAME_SIZE - 1;
 
 const PAGE_SIZE: usize = 4096;
 const TABLE_MAX_PAGES: usize = 100;
-const ROWS_PER_PAGE: usize = PAGE_SIZE / ROW_SIZE;
+const ROWS_PER_PAGE: usize = LEAF_NODE_MAX_CELLS;
 const TABLE_MAX_ROWS: usize = ROWS_PER_PAGE * TABLE_MAX_PAGES;
+/// Rows a table keeps deserialized for repeated selects.
+const ROW_CACHE_CAPACITY: usize = 64;
+
+// Page Footer Layout
+// Each data page ends in a CRC32 of the bytes before it.
+const PAGE_CHECKSUM_SIZE: usize = size_of::<u32>();
+const PAGE_CHECKSUM_OFFSET: usize = PAGE_SIZE - PAGE_CHECKSUM_SIZE;
+const PAGE_USABLE_SIZE: usize = PAGE_CHECKSUM_OFFSET;
+
+// File Header Layout
+// The header fills the first page of the file, so data page `n` starts at file page `n + 1`.
+const HEADER_SIZE: usize = PAGE_SIZE;
+const HEADER_MAGIC: &[u8; 8] = b"try-db\0\0";
+const HEADER_MAGIC_OFFSET: usize = 0;
+const HEADER_VERSION_SIZE: usize = size_of::<u32>();
+const HEADER_VERSION_OFFSET: usize = HEADER_MAGIC_OFFSET + HEADER_MAGIC.len();
+const HEADER_SCHEMA_SIZE: usize = size_of::<u32>();
+const HEADER_SCHEMA_OFFSET: usize = HEADER_VERSION_OFFSET + HEADER_VERSION_SIZE;
+const HEADER_NUM_ROWS_SIZE: usize = size_of::<u32>();
+const HEADER_NUM_ROWS_OFFSET: usize = HEADER_SCHEMA_OFFSET + HEADER_SCHEMA_SIZE;
+const HEADER_FILL_BYTE_OFFSET: usize = HEADER_NUM_ROWS_OFFSET + HEADER_NUM_ROWS_SIZE;
+const FORMAT_VERSION: u32 = 8;
 // const NUM_ROWS_FILLED_FOR_PAGE_OFFSET: usize = 0;
 // const NUM_ROWS_FILLED_FOR_PAGE_SIZE: usize =  size_of::<i32>();
 
 // Common Node Header Layout
+// Every data page is a node and starts with this header.
 const NODE_TYPE_SIZE: usize = size_of::<i8>();
 const NODE_TYPE_OFFSET: usize = 0;
 const IS_ROOT_SIZE: usize = size_of::<i8>();
 const IS_ROOT_OFFSET: usize = NODE_TYPE_SIZE;
 const PARENT_POINTER_SIZE: usize = size_of::<i32>();
 const PARENT_POINTER_OFFSET: usize = IS_ROOT_OFFSET + IS_ROOT_SIZE;
-const COMMON_NODE_HEADER_SIZE: usize = NODE_TYPE_SIZE + IS_ROOT_OFFSET + PARENT_POINTER_SIZE;
+const COMMON_NODE_HEADER_SIZE: usize = NODE_TYPE_SIZE + IS_ROOT_SIZE + PARENT_POINTER_SIZE;
 
 // Leaf Node Header Layout
 const LEAF_NODE_NUM_CELLS_SIZE: usize = size_of::<i32>();
@@ -46,16 +119,223 @@ const LEAF_NODE_HEADER_SIZE: usize = COMMON_NODE_HEADER_SIZE + LEAF_NODE_NUM_CEL
 /*
     Leaf Node Body Layout
 */
-const LEAF_NODE_KEY_SIZE: usize = size_of::<i32>();
-const LEAF_NODE_KEY_OFFSET: usize = 0;
+const LEAF_NODE_KEY_SIZE: usize = size_of::<u32>();
 const LEAF_NODE_VALUE_SIZE: usize = ROW_SIZE;
-const LEAF_NODE_VALUE_OFFSET: usize = LEAF_NODE_KEY_OFFSET + LEAF_NODE_KEY_SIZE;
 const LEAF_NODE_CELL_SIZE: usize = LEAF_NODE_KEY_SIZE + LEAF_NODE_VALUE_SIZE;
-const LEAF_NODE_SPACE_FOR_CELLS: usize = PAGE_SIZE - LEAF_NODE_HEADER_SIZE;
+const LEAF_NODE_SPACE_FOR_CELLS: usize = PAGE_USABLE_SIZE - LEAF_NODE_HEADER_SIZE;
 const LEAF_NODE_MAX_CELLS: usize = LEAF_NODE_SPACE_FOR_CELLS / LEAF_NODE_CELL_SIZE;
 
+// Internal Node Header Layout
+const INTERNAL_NODE_NUM_KEYS_SIZE: usize = size_of::<u32>();
+const INTERNAL_NODE_NUM_KEYS_OFFSET: usize = COMMON_NODE_HEADER_SIZE;
+const INTERNAL_NODE_RIGHT_CHILD_SIZE: usize = size_of::<u32>();
+const INTERNAL_NODE_RIGHT_CHILD_OFFSET: usize =
+    INTERNAL_NODE_NUM_KEYS_OFFSET + INTERNAL_NODE_NUM_KEYS_SIZE;
+const INTERNAL_NODE_RIGHT_CHILD_ROWS_SIZE: usize = size_of::<u32>();
+const INTERNAL_NODE_RIGHT_CHILD_ROWS_OFFSET: usize =
+    INTERNAL_NODE_RIGHT_CHILD_OFFSET + INTERNAL_NODE_RIGHT_CHILD_SIZE;
+const INTERNAL_NODE_HEADER_SIZE: usize = COMMON_NODE_HEADER_SIZE
+    + INTERNAL_NODE_NUM_KEYS_SIZE
+    + INTERNAL_NODE_RIGHT_CHILD_SIZE
+    + INTERNAL_NODE_RIGHT_CHILD_ROWS_SIZE;
+
+/*
+    Internal Node Body Layout
+*/
+const INTERNAL_NODE_CHILD_SIZE: usize = size_of::<u32>();
+const INTERNAL_NODE_KEY_SIZE: usize = size_of::<u32>();
+const INTERNAL_NODE_ROWS_SIZE: usize = size_of::<u32>();
+const INTERNAL_NODE_CELL_SIZE: usize =
+    INTERNAL_NODE_CHILD_SIZE + INTERNAL_NODE_KEY_SIZE + INTERNAL_NODE_ROWS_SIZE;
+const INTERNAL_NODE_MAX_CELLS: usize =
+    (PAGE_USABLE_SIZE - INTERNAL_NODE_HEADER_SIZE) / INTERNAL_NODE_CELL_SIZE;
+
+const DB_NAME_ENV: &str = "TRYDB_DATABASE";
+const DATA_DIR_ENV: &str = "TRYDB_DATA_DIR";
+const DEFAULT_DATA_DIR: &str = "db";
+const LOG_ENV: &str = "TRYDB_LOG";
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum LogLevel {
+    Error,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    fn from_verbosity(verbose: u8) -> Self {
+        match verbose {
+            0 => LogLevel::Error,
+            1 => LogLevel::Info,
+            _ => LogLevel::Debug,
+        }
+    }
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "error" => Some(LogLevel::Error),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Error as u8);
+
+fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+const DEFAULT_PROMPT: &str = "db -> ";
+const DEFAULT_CONTINUATION_PROMPT: &str = "   ... ";
+
+/// Commands `.history` lists when no count is given.
+const DEFAULT_HISTORY_LENGTH: usize = 20;
+
+/// Byte that pads the unused end of username and email fields in files created this
+/// session, set by `--fill-byte`. A file keeps the byte it was created with in its header.
+static FILL_BYTE: AtomicU8 = AtomicU8::new(0);
+
+fn set_fill_byte(fill: u8) {
+    FILL_BYTE.store(fill, Ordering::Relaxed);
+}
+
+fn fill_byte() -> u8 {
+    FILL_BYTE.load(Ordering::Relaxed)
+}
+
+/// Whether `fill` can pad text fields: a control character, which `validate_row` keeps
+/// out of values, or a byte UTF-8 never uses. Either way no stored value can hold it.
+fn is_valid_fill_byte(fill: u8) -> bool {
+    fill.is_ascii_control() || matches!(fill, 0xc0 | 0xc1 | 0xf5..=0xff)
+}
+
+fn log_enabled(level: LogLevel) -> bool {
+    level as u8 <= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+#[cfg(not(test))]
+fn write_log(level: LogLevel, args: fmt::Arguments) {
+    eprintln!("[{:?}] {}", level, args);
+}
+
+#[cfg(test)]
+thread_local! {
+    static LOG_OUTPUT: std::cell::RefCell<String> = const { std::cell::RefCell::new(String::new()) };
+}
+
+#[cfg(test)]
+fn write_log(level: LogLevel, args: fmt::Arguments) {
+    LOG_OUTPUT.with(|log| {
+        use std::fmt::Write;
+        writeln!(log.borrow_mut(), "[{:?}] {}", level, args).unwrap();
+    });
+}
+
+#[derive(Debug, Default)]
+struct CliOptions {
+    db_name: Option<String>,
+    verbose: u8,
+    /// Schema for a newly created file; an existing file must already use it.
+    schema: Option<Schema>,
+    force: bool,
+    append_only: bool,
+    /// Skips the startup banner.
+    quiet: bool,
+    /// Opens the table read-only, which wins over `--append-only`.
+    readonly: bool,
+    /// Opens the db name as a path instead of a file in the `db` directory.
+    no_subdir: bool,
+    /// Pads unused field space in files created this session, decimal or `0x` hex. Only
+    /// bytes `is_valid_fill_byte` accepts are taken.
+    fill_byte: u8,
+    /// Replaces the default `db -> ` prompt.
+    prompt: Option<String>,
+    /// Emits JSON lines for drivers: no banner, prompt or timing.
+    json: bool,
+    /// Syncs the file after every flush, as `.pragma synchronous on` does.
+    durable: bool,
+    /// Caps the rows the table accepts below what its layout fits.
+    max_rows: Option<usize>,
+    /// Seconds between automatic saves; 0 leaves saving to close. The interval is checked
+    /// after each command, so an idle prompt saves with the next command.
+    autosave_secs: u64,
+}
+
+impl CliOptions {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = CliOptions::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--verbose" => options.verbose += 1,
+                "--force" => options.force = true,
+                "--append-only" => options.append_only = true,
+                "--quiet" => options.quiet = true,
+                "--readonly" => options.readonly = true,
+                "--no-subdir" => options.no_subdir = true,
+                "--durable" => options.durable = true,
+                "--max-rows" => {
+                    let value = args.next().unwrap_or_default();
+                    match value.parse() {
+                        Ok(max_rows) => options.max_rows = Some(max_rows),
+                        Err(_) => return Err(format!("Invalid max rows: {:?}", value)),
+                    }
+                }
+                "--fill-byte" => {
+                    let value = args.next().unwrap_or_default();
+                    let parsed = match value.strip_prefix("0x") {
+                        Some(hex) => u8::from_str_radix(hex, 16),
+                        None => value.parse(),
+                    };
+                    match parsed {
+                        Ok(fill) if is_valid_fill_byte(fill) => options.fill_byte = fill,
+                        _ => return Err(format!("Invalid fill byte: {:?}", value)),
+                    }
+                }
+                "--prompt" => options.prompt = args.next(),
+                "--json" => options.json = true,
+                "--autosave-secs" => {
+                    let value = args.next().unwrap_or_default();
+                    match value.parse() {
+                        Ok(secs) => options.autosave_secs = secs,
+                        Err(_) => return Err(format!("Invalid autosave interval: {:?}", value)),
+                    }
+                }
+                "--schema" => {
+                    let name = args.next().unwrap_or_default();
+                    match Schema::from_name(&name) {
+                        Some(schema) => options.schema = Some(schema),
+                        None => return Err(format!("Unknown schema: {:?}", name)),
+                    }
+                }
+                _ if arg.len() > 1
+                    && arg.starts_with('-')
+                    && arg[1..].bytes().all(|b| b == b'v') =>
+                {
+                    options.verbose += arg.len() as u8 - 1
+                }
+                _ if options.db_name.is_none() => options.db_name = Some(arg),
+                _ => {}
+            }
+        }
+        Ok(options)
+    }
+    /// `--verbose` wins over `TRYDB_LOG`; with neither only errors are logged.
+    fn log_level(&self) -> LogLevel {
+        if self.verbose > 0 {
+            return LogLevel::from_verbosity(self.verbose);
+        }
+        env::var(LOG_ENV)
+            .ok()
+            .and_then(|name| LogLevel::from_name(&name))
+            .unwrap_or(LogLevel::Error)
+    }
+}
+
 enum MetaCommandResult {
     MetaCommandSuccess,
+    MetaCommandHandled,
     MetaCommandUnrecognizedCommand,
     MetaNoCommand,
 }
@@ -63,23 +343,76 @@ enum MetaCommandResult {
 #[derive(Debug)]
 enum StatementType {
     StatementInsert,
+    StatementInsertOrReplace,
+    StatementInsertRaw,
     StatementSelect,
     StatementSelectWithEmail,
+    StatementSelectRowid,
+    StatementSelectTail,
+    StatementSelectDistinctUsername,
+    StatementSelectCountByUsername,
+    StatementSelectRaw,
+    StatementSelectInto,
+    StatementUpdate,
+    StatementDelete,
 }
 
+#[derive(Debug)]
 enum PrepareResult {
     PrepareSuccess,
     PrepareUnrecognizedStatement,
     PrepareSyntaxError,
+    /// A syntax error found at this byte offset of the statement.
+    PrepareSyntaxErrorAt(usize),
     PrepareStringTooLong,
     PrepareNegativeId,
+    /// A username or email is empty.
+    PrepareEmptyValue,
 }
 
 #[derive(Debug)]
 enum ExecuteResult {
-    ExecuteSuccess,
     ExecuteTableFull,
     ExecuteFail,
+    ExecuteReadOnly,
+    /// The table is append-only and the statement would change a stored row.
+    ExecuteAppendOnly,
+    /// A row with the inserted id is already stored.
+    ExecuteDuplicateKey,
+    /// No row has the id the statement names.
+    ExecuteNotFound,
+}
+
+/// What an executed statement produced; `process_input` prints it in the session's
+/// output mode.
+#[derive(Debug, PartialEq)]
+enum QueryResult {
+    /// Rows written by an insert or a replace.
+    Inserted(usize),
+    /// Rows changed by an update.
+    Updated(usize),
+    /// Rows removed by a delete.
+    Deleted(usize),
+    /// Users rows with their row numbers.
+    Selected(Vec<(usize, Row)>),
+    /// The folded ids, or `None` when there were no rows.
+    Aggregated(Aggregate, Option<f64>),
+    /// Rows copied into a new database by `select into`.
+    Copied {
+        file_name: String,
+        rows: usize,
+    },
+    /// Users rows as their stored bytes, with their row numbers.
+    SelectedRaw(Vec<(usize, RawRow)>),
+    SelectedProducts(Vec<(usize, Product)>),
+    /// The computed value of every row, with its row number.
+    Computed(Computed, Vec<(usize, usize)>),
+    /// Distinct usernames in the order they first appear.
+    Usernames(Vec<String>),
+    /// How many rows each username has, sorted by username.
+    Counts(Vec<(String, usize)>),
+    /// The first row with the searched email and its row number.
+    Found(Option<(usize, Row)>),
 }
 
 #[derive(Debug)]
@@ -91,27 +424,220 @@ enum Error {
     ExecuteError,
     PrepareStringTooLong,
     PrepareNegativeId,
+    PrepareEmptyValue,
+    PrepareUnrecognizedStatement,
     TableFull,
     DbOpenError,
+    /// Something other than a directory sits where the db directory should be.
+    DbDirNotADirectory,
+    /// The name isn't a single file name inside the db directory.
+    InvalidDbName,
+    DbCorruptFile,
+    /// The file doesn't start with a header this version can read.
+    DbUnsupportedFormat,
+    /// A data page read from disk doesn't match the checksum in its footer.
+    ChecksumMismatch,
+    /// The page lies past the last one a table can hold.
+    PageOutOfBounds,
+    SchemaMismatch,
+    /// The table was opened append-only and the statement would change existing rows.
+    AppendOnly,
+    /// The table was opened read-only and the statement would change it.
+    ReadOnly,
+    DuplicateKey,
+    /// No row has the id a delete or an update names.
+    NotFound,
 }
-enum NodeType {
-    NodInternal,
-    NodeLeaf,
+/// The table schemas a database file can hold. The schema is chosen when the file is
+/// created and recorded in its header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Schema {
+    Users,
+    Products,
 }
 
-#[derive(Debug)]
-struct Row {
+impl Schema {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "users" => Some(Schema::Users),
+            "products" => Some(Schema::Products),
+            _ => None,
+        }
+    }
+    fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(Schema::Users),
+            1 => Some(Schema::Products),
+            _ => None,
+        }
+    }
+    fn id(self) -> u32 {
+        self as u32
+    }
+    fn layout(self) -> Layout {
+        match self {
+            Schema::Users => Layout::for_row_size(ROW_SIZE),
+            Schema::Products => Layout::for_row_size(PRODUCT_ROW_SIZE),
+        }
+    }
+}
+
+/// How rows of one schema are packed into leaf pages, one cell per row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Layout {
+    row_size: usize,
+    /// A cell's key followed by its row.
+    cell_size: usize,
+    rows_per_page: usize,
+    max_rows: usize,
+}
+
+impl Layout {
+    const fn for_row_size(row_size: usize) -> Self {
+        let cell_size = LEAF_NODE_KEY_SIZE + row_size;
+        let rows_per_page = LEAF_NODE_SPACE_FOR_CELLS / cell_size;
+        Layout {
+            row_size,
+            cell_size,
+            rows_per_page,
+            max_rows: rows_per_page * TABLE_MAX_PAGES,
+        }
+    }
+    /// Cells a full leaf keeps when it splits to take one more. The upper half moves
+    /// to the new leaf, which gets the extra cell when the total is odd.
+    fn left_split_count(&self) -> usize {
+        self.rows_per_page.div_ceil(2)
+    }
+    fn cell_offset(&self, cell_num: usize) -> usize {
+        LEAF_NODE_HEADER_SIZE + cell_num * self.cell_size
+    }
+    /// Byte offset of the row in cell `cell_num`, past the cell's key.
+    fn value_offset(&self, cell_num: usize) -> usize {
+        self.cell_offset(cell_num) + LEAF_NODE_KEY_SIZE
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Product {
     id: i32,
-    username: String,
-    email: String,
+    name: String,
+    price: f64,
 }
 
-impl Row {
+impl Product {
     fn new() -> Self {
-        Row {
+        Product {
             id: 0,
-            username: String::with_capacity(32),
-            email: String::with_capacity(255),
+            name: String::with_capacity(PRODUCT_NAME_SIZE),
+            price: 0.0,
+        }
+    }
+    fn fields(&self) -> Vec<Field> {
+        vec![
+            Field {
+                name: "id",
+                value: self.id.to_string(),
+                is_text: false,
+            },
+            Field {
+                name: "name",
+                value: self.name.clone(),
+                is_text: true,
+            },
+            Field {
+                name: "price",
+                value: self.price.to_string(),
+                is_text: false,
+            },
+        ]
+    }
+}
+
+/// A filter on the rows a `select` prints.
+#[derive(Debug)]
+enum Predicate {
+    /// Usernames containing the pattern, ignoring case. The pattern is stored lowercased.
+    UsernameLike(String),
+    /// Emails equal to this one.
+    EmailEquals(String),
+    /// The row with this id.
+    IdEquals(u32),
+}
+
+impl Predicate {
+    fn matches(&self, row: &Row) -> bool {
+        match self {
+            Predicate::UsernameLike(pattern) => row.username().to_lowercase().contains(pattern),
+            Predicate::EmailEquals(email) => row.email() == *email,
+            Predicate::IdEquals(id) => row.id() == *id,
+        }
+    }
+}
+
+/// A single value `select` folds every row's id into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Aggregate {
+    Min,
+    Max,
+    Avg,
+}
+impl Aggregate {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "min(id)" => Some(Aggregate::Min),
+            "max(id)" => Some(Aggregate::Max),
+            "avg(id)" => Some(Aggregate::Avg),
+            _ => None,
+        }
+    }
+    fn name(self) -> &'static str {
+        match self {
+            Aggregate::Min => "min(id)",
+            Aggregate::Max => "max(id)",
+            Aggregate::Avg => "avg(id)",
+        }
+    }
+}
+
+/// A value `select` derives from each row instead of printing the row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Computed {
+    /// Characters in a text column.
+    Len(TextColumn),
+}
+
+impl Computed {
+    fn name(self) -> &'static str {
+        match self {
+            Computed::Len(TextColumn::Username) => "len(username)",
+            Computed::Len(TextColumn::Email) => "len(email)",
+        }
+    }
+    fn evaluate(self, row: &Row) -> usize {
+        match self {
+            Computed::Len(column) => column.value(row).chars().count(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TextColumn {
+    Username,
+    Email,
+}
+
+impl TextColumn {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "username" => Some(TextColumn::Username),
+            "email" => Some(TextColumn::Email),
+            _ => None,
+        }
+    }
+    fn value(self, row: &Row) -> &str {
+        match self {
+            TextColumn::Username => row.username(),
+            TextColumn::Email => row.email(),
         }
     }
 }
@@ -119,18 +645,54 @@ impl Row {
 #[derive(Debug)]
 struct Statement {
     statement_type: Option<StatementType>,
+    /// Schema of the table the statement runs against; decides how `insert` is parsed.
+    schema: Schema,
+    /// Padding byte of that table, which `insert raw` values can't hold.
+    fill: u8,
     row_to_insert: Row,
+    /// Row `insert raw <id> <hex> <hex>` stores byte for byte.
+    raw_row_to_insert: Option<RawRow>,
+    product_to_insert: Product,
+    /// Position in id order targeted by `select rowid <n>`.
+    row_num: usize,
+    /// How many rows with the highest ids `select tail <n>` prints.
+    tail: usize,
+    /// Email looked up by `select <email>`.
+    email: String,
+    /// Printed instead of the whole row, e.g. by `select len(username)`.
+    computed: Option<Computed>,
+    predicate: Option<Predicate>,
+    /// Folds the rows into one value instead, e.g. for `select max(id)`.
+    aggregate: Option<Aggregate>,
+    /// New database `select into <name>` copies the selected rows into.
+    into: Option<String>,
+    /// Id of the row `update <id> set ...` changes.
+    update_id: u32,
+    /// Columns `update` sets and their new values; the others are left as stored.
+    assignments: Vec<(TextColumn, String)>,
+    /// Id of the row `delete <id>` removes.
+    delete_id: u32,
 }
 
 impl Statement {
     fn new() -> Statement {
         Statement {
             statement_type: None,
-            row_to_insert: Row {
-                id: 0,
-                username: String::with_capacity(32),
-                email: String::with_capacity(255),
-            },
+            schema: Schema::Users,
+            fill: 0,
+            row_to_insert: Row::new(),
+            raw_row_to_insert: None,
+            product_to_insert: Product::new(),
+            row_num: 0,
+            tail: 0,
+            email: String::new(),
+            computed: None,
+            predicate: None,
+            aggregate: None,
+            into: None,
+            update_id: 0,
+            assignments: Vec::new(),
+            delete_id: 0,
         }
     }
 }
@@ -152,550 +714,5757 @@ impl InputBuffer {
     }
 }
 
+/// How `select` prints rows, chosen with `.mode`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputMode {
+    /// `Row <n> <debug form of the row>`.
+    Default,
+    /// Fields separated by `|`.
+    List,
+    /// Fields padded or cut to the `.width` setting.
+    Column,
+    /// One JSON object per row.
+    Json,
+    /// An `insert` statement per row that can be fed back into the REPL.
+    Insert,
+}
+
+impl OutputMode {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(OutputMode::Default),
+            "list" => Some(OutputMode::List),
+            "column" => Some(OutputMode::Column),
+            "json" => Some(OutputMode::Json),
+            "insert" => Some(OutputMode::Insert),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OutputFormat {
+    mode: OutputMode,
+    /// Display width of each field in column mode.
+    width: usize,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat {
+            mode: OutputMode::Default,
+            width: 20,
+        }
+    }
+}
+
+/// One printed column of a row. Text fields are quoted in JSON output.
+struct Field {
+    name: &'static str,
+    value: String,
+    is_text: bool,
+}
+
+/// State that lives for the whole REPL run, threaded through every command.
+struct Session<W: Write> {
+    table: Table,
+    timer: bool,
+    /// Prints each command before running it, set by `.echo on`.
+    echo: bool,
+    /// How many `.read` scripts are currently running inside each other.
+    read_depth: usize,
+    /// Status the process exits with, set by `.exit <code>`.
+    exit_code: u8,
+    /// Rows affected by the last write, shown by `.changes`; 0 when it matched no row.
+    changes: usize,
+    /// Skips the confirmation prompt of destructive commands such as `.clear`.
+    force: bool,
+    format: OutputFormat,
+    out: W,
+    /// Where the REPL reads statements and confirmation answers from.
+    input: Box<dyn BufRead>,
+    /// Commands entered at the prompt, oldest first, listed by `.history`.
+    history: Vec<String>,
+    /// Shown before each command, set by `--prompt` or `.prompt`.
+    prompt: String,
+    /// Prints statement results and errors as one JSON object per line, set by `--json`.
+    json: bool,
+    /// Shown before each further line of a command that spans lines, set by the second
+    /// argument of `.prompt`. Commands are single lines for now.
+    continuation_prompt: String,
+}
+
+impl<W: Write> Session<W> {
+    fn new(table: Table, out: W) -> Self {
+        Session {
+            table,
+            timer: false,
+            echo: false,
+            read_depth: 0,
+            exit_code: 0,
+            changes: 0,
+            force: false,
+            format: OutputFormat::default(),
+            out,
+            input: Box::new(io::empty()),
+            history: Vec::new(),
+            prompt: DEFAULT_PROMPT.to_owned(),
+            json: false,
+            continuation_prompt: DEFAULT_CONTINUATION_PROMPT.to_owned(),
+        }
+    }
+    fn with_input(mut self, input: impl BufRead + 'static) -> Self {
+        self.input = Box::new(input);
+        self
+    }
+}
+
+/// Bytes behind a pager: a file on disk, or a buffer for tables that live in memory.
+trait Storage: Read + Write + Seek + fmt::Debug {
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
+    /// Makes written bytes durable, e.g. past a power failure.
+    fn sync_all(&mut self) -> io::Result<()>;
+}
+
+impl Storage for File {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+    fn sync_all(&mut self) -> io::Result<()> {
+        File::sync_all(self)
+    }
+}
+
+impl Storage for io::Cursor<Vec<u8>> {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.get_mut().resize(len as usize, 0);
+        Ok(())
+    }
+    fn sync_all(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 struct Pager {
-    file: Rc<File>,
+    file: Box<dyn Storage>,
+    /// Bytes of the file in use; after a clear the file is longer until it is closed.
     file_length: u64,
+    /// Data pages the table's tree uses, on disk or allocated since.
+    num_pages: usize,
     pages: Vec<Option<Box<[u8; PAGE_SIZE]>>>,
+    /// Bytes of each resident page modified since it was last flushed.
+    dirty: Vec<Option<Range<usize>>>,
+    metrics: PagerMetrics,
+    /// Sync storage after flushing, set with `.pragma synchronous on`. Off by default
+    /// since every sync waits for the disk.
+    durable: bool,
+}
+
+/// Page I/O done by a pager since it was opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct PagerMetrics {
+    /// Pages loaded from storage; hits on resident pages aren't counted.
+    pages_read: u64,
+    pages_written: u64,
+}
+
+/// How to open a table file, built up option by option and finished with `open`.
+/// Options left unset keep the defaults: the schema the file records, or users for a
+/// new file, and a table that can be changed freely.
+#[derive(Debug, Clone, Default)]
+struct TableConfig {
+    schema: Option<Schema>,
+    read_only: bool,
+    append_only: bool,
+    durable: bool,
+    max_rows: Option<usize>,
+}
+
+impl TableConfig {
+    fn new() -> Self {
+        TableConfig::default()
+    }
+    /// Requires the file to hold `schema`, creating it with that schema if it's new.
+    fn schema(mut self, schema: Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+    fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+    fn append_only(mut self, append_only: bool) -> Self {
+        self.append_only = append_only;
+        self
+    }
+    /// Syncs the file after every flush, like `pragma synchronous on`.
+    fn durable(mut self, durable: bool) -> Self {
+        self.durable = durable;
+        self
+    }
+    fn max_rows(mut self, max_rows: usize) -> Self {
+        self.max_rows = Some(max_rows);
+        self
+    }
+    fn open(&self, file_name: &str) -> Result<Table, Error> {
+        let mut table = Table::open(file_name, self.schema)?
+            .with_read_only(self.read_only)
+            .with_append_only(self.append_only);
+        if let Some(max_rows) = self.max_rows {
+            table = table.with_max_rows(max_rows);
+        }
+        table.pager.durable = self.durable;
+        Ok(table)
+    }
 }
 
 #[derive(Debug)]
 struct Table {
     num_rows: usize,
+    schema: Schema,
+    layout: Layout,
+    /// Rows the table accepts; defaults to what the layout fits in `TABLE_MAX_PAGES`.
+    max_rows: usize,
+    /// Only inserts may change an append-only table; clearing or removing rows fails.
+    append_only: bool,
+    /// Nothing may change a read-only table, inserts included.
+    read_only: bool,
     pager: Pager,
+    /// Name of the backing file inside the db directory; `None` for in-memory tables.
+    file_name: Option<String>,
+    row_cache: RowCache,
+    /// Pads the unused end of users text fields, as recorded in the header.
+    fill: u8,
+}
+
+/// Users rows deserialized by recent selects, keyed by row number. Once full, the least
+/// recently used row is evicted.
+#[derive(Debug)]
+struct RowCache {
+    capacity: usize,
+    /// Least recently used first.
+    entries: VecDeque<(usize, Row)>,
+    hits: u64,
+}
+
+impl RowCache {
+    fn new(capacity: usize) -> Self {
+        RowCache {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            hits: 0,
+        }
+    }
+    fn get(&mut self, row_num: usize) -> Option<Row> {
+        let position = self
+            .entries
+            .iter()
+            .position(|(cached, _)| *cached == row_num)?;
+        let entry = self.entries.remove(position)?;
+        let row = entry.1.clone();
+        self.entries.push_back(entry);
+        self.hits += 1;
+        Some(row)
+    }
+    fn insert(&mut self, row_num: usize, row: Row) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((row_num, row));
+    }
+    fn invalidate(&mut self, row_num: usize) {
+        self.entries.retain(|(cached, _)| *cached != row_num);
+    }
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
 }
 
 impl Pager {
-    fn new(file: Rc<File>, file_length: u64) -> Self {
+    fn new(file: Box<dyn Storage>, file_length: u64) -> Self {
+        let num_pages = file_length.saturating_sub(HEADER_SIZE as u64) / PAGE_SIZE as u64;
         Pager {
             file,
             file_length,
+            num_pages: (num_pages as usize).min(TABLE_MAX_PAGES),
             pages: vec![None; TABLE_MAX_PAGES],
+            dirty: vec![None; TABLE_MAX_PAGES],
+            metrics: PagerMetrics::default(),
+            durable: false,
         }
     }
-    fn pager_flush(&mut self, page_num: usize, page_size: usize) -> io::Result<()> {
-        if page_num > TABLE_MAX_PAGES {
-            eprintln!("Tried to flush a out of bound page");
-            std::process::exit(1);
-        }
-        if self.pages[page_num].is_none() {
-            eprintln!("Tried to flush null page");
-            std::process::exit(1);
-        }
-        let offset = (page_num * PAGE_SIZE) as u64;
-        let page = self.pages[page_num].as_ref().unwrap();
-        let file = Rc::get_mut(&mut self.file).unwrap();
-        file.seek(SeekFrom::Start(offset))?;
-        println!("{:?}", &page[page_num]);
-        let bytes_written = file.write(&page[..page_size])?;
-        if bytes_written != page_size {
-            eprintln!(
-                "Error writing: only {} bytes written out of {}",
-                bytes_written, page_size
-            );
-            std::process::exit(1);
+    fn metrics(&self) -> PagerMetrics {
+        self.metrics
+    }
+    fn mark_dirty(&mut self, page_num: usize, range: Range<usize>) {
+        let dirty = &mut self.dirty[page_num];
+        *dirty = match dirty.take() {
+            Some(current) => Some(current.start.min(range.start)..current.end.max(range.end)),
+            None => Some(range),
+        };
+    }
+    fn sync_if_durable(&mut self) -> io::Result<()> {
+        if self.durable {
+            self.file.sync_all()?;
         }
         Ok(())
     }
-}
-
-fn get_page(pager: &mut Pager, page_num: usize) -> Result<&mut [u8; PAGE_SIZE], io::Error> {
-    if *&pager.pages[page_num].is_none() {
-        let mut page: Box<[u8; PAGE_SIZE]> = Box::new([0; PAGE_SIZE]);
-        let mut num_pages = pager.file_length as usize / PAGE_SIZE;
-        if pager.file_length as usize % PAGE_SIZE != 0 {
-            num_pages += 1;
+    /// Flushes every dirty page in `pages`, writing each run of contiguous dirty pages
+    /// with a single `write` instead of one per page. When a run fails its pages are
+    /// retried one by one, and the pages that still fail are returned with their error.
+    fn flush_dirty_pages(&mut self, pages: Range<usize>) -> Vec<(usize, io::Error)> {
+        let mut failures = Vec::new();
+        let mut page_num = pages.start;
+        while page_num < pages.end {
+            if self.dirty[page_num].is_none() {
+                page_num += 1;
+                continue;
+            }
+            let run_start = page_num;
+            while page_num < pages.end && self.dirty[page_num].is_some() {
+                page_num += 1;
+            }
+            if self.flush_run(run_start..page_num).is_err() {
+                for retry in run_start..page_num {
+                    if let Err(err) = self.flush_run(retry..retry + 1) {
+                        failures.push((retry, err));
+                    }
+                }
+            }
+        }
+        failures
+    }
+    /// Writes a run of dirty pages as one buffer, starting at the first page's dirty bytes.
+    /// The pages stay dirty if the write fails. A run reaching past `TABLE_MAX_PAGES` or
+    /// holding a page that isn't loaded is rejected before anything is written.
+    fn flush_run(&mut self, run: Range<usize>) -> io::Result<()> {
+        if run.end > TABLE_MAX_PAGES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("pages {:?} are out of bounds", run),
+            ));
         }
-        if page_num < num_pages {
-            let offset = (page_num * PAGE_SIZE) as u64;
-            let mut file = Rc::get_mut(&mut pager.file).unwrap();
-            file.seek(SeekFrom::Start(offset))?;
-            file.read_exact(&mut *page).unwrap()
+        if let Some(page_num) = run.clone().find(|&page_num| self.pages[page_num].is_none()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("page {} isn't loaded", page_num),
+            ));
         }
-        pager.pages[page_num] = Some(page);
+        let run_offset = page_offset(run.start);
+        let start = match &self.dirty[run.start] {
+            Some(range) if run_offset + PAGE_SIZE as u64 <= self.file_length => range.start,
+            _ => 0,
+        };
+        let mut buffer = Vec::with_capacity(run.len() * PAGE_SIZE);
+        for page_num in run.clone() {
+            let page = self.pages[page_num].as_mut().unwrap();
+            stamp_checksum(page);
+            buffer.extend_from_slice(&page[..]);
+        }
+        log!(
+            LogLevel::Debug,
+            "Flushing pages {:?} from byte {}",
+            run,
+            start
+        );
+        let file = &mut self.file;
+        file.seek(SeekFrom::Start(run_offset + start as u64))?;
+        file.write_all(&buffer[start..])?;
+        self.dirty[run.clone()].fill(None);
+        self.file_length = self.file_length.max(page_offset(run.end));
+        self.metrics.pages_written += run.len() as u64;
+        Ok(())
     }
-    Ok(pager.pages[page_num].as_mut().unwrap())
-}
-
-fn pager_open(filename: &str) -> io::Result<Pager> {
-    let db_dir = Path::new("db");
-    // Create the db directory if it doesn't exist
-    create_dir_all(db_dir)?;
-    let file_path = db_dir.join(filename);
-    let mut file = Rc::new(
-        OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .mode(0o600)
-            .open(file_path)?,
-    );
-    let file_length = Rc::get_mut(&mut file).unwrap().seek(SeekFrom::End(0))?;
-    Ok(Pager::new(file, file_length))
-}
-
-fn get_num_rows(pager: &mut Pager) -> usize {
-    let file = Rc::get_mut(&mut pager.file).unwrap();
-    let mut num_rows = 0;
-    for i in (0..pager.file_length).step_by(ROW_SIZE) {
-        let mut row = [0; ROW_SIZE];
-        file.seek(SeekFrom::Start(i))
-            .expect("Some error while seeking");
-        file.read(&mut row).expect("error while reading");
-        if is_empty_row(&row) {
-            return num_rows;
+    /// Forgets every data page. The file is cut back to the header on close.
+    fn discard_pages(&mut self) {
+        self.pages.fill(None);
+        self.dirty.fill(None);
+        self.num_pages = 0;
+        self.file_length = self.file_length.min(HEADER_SIZE as u64);
+    }
+    /// Drops the pages from `page_num` on, resident or on disk, and returns how many
+    /// were resident. The file is only cut once `truncate_to_length` runs.
+    fn shrink_to(&mut self, page_num: usize) -> usize {
+        let resident = self.pages[page_num..].iter().flatten().count();
+        self.pages[page_num..].fill(None);
+        self.dirty[page_num..].fill(None);
+        self.num_pages = self.num_pages.min(page_num);
+        self.file_length = self.file_length.min(page_offset(page_num));
+        resident
+    }
+    /// Takes the page after the last one in use, zeroed and marked dirty, or `None`
+    /// once `TABLE_MAX_PAGES` are in use.
+    fn allocate_page(&mut self) -> Option<usize> {
+        let page_num = self.num_pages;
+        if page_num >= TABLE_MAX_PAGES {
+            return None;
         }
-        num_rows += 1;
+        self.pages[page_num] = Some(Box::new([0; PAGE_SIZE]));
+        self.mark_dirty(page_num, 0..PAGE_USABLE_SIZE);
+        self.num_pages += 1;
+        Some(page_num)
+    }
+    #[cfg(test)]
+    fn resident_pages(&self) -> usize {
+        self.pages.iter().flatten().count()
+    }
+    /// Drops anything on disk past `file_length`.
+    fn truncate_to_length(&mut self) -> io::Result<()> {
+        self.file.set_len(self.file_length)
+    }
+    fn read_header(&mut self) -> io::Result<Box<[u8; HEADER_SIZE]>> {
+        let mut header = Box::new([0; HEADER_SIZE]);
+        let file = &mut self.file;
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header[..])?;
+        Ok(header)
+    }
+    fn write_header(&mut self, header: &[u8; HEADER_SIZE]) -> io::Result<()> {
+        let file = &mut self.file;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(header)?;
+        self.file_length = self.file_length.max(HEADER_SIZE as u64);
+        Ok(())
     }
-    num_rows
 }
 
-fn is_empty_row(row: &[u8]) -> bool {
-    let mut is_empty = true;
-    for i in row {
-        if i & 1 != 0 {
-            is_empty = false;
-            break;
-        }
-    }
-    is_empty
+/// File offset of data page `page_num`, which sits after the header.
+fn page_offset(page_num: usize) -> u64 {
+    (HEADER_SIZE + page_num * PAGE_SIZE) as u64
 }
 
-impl Table {
-    fn new() -> Self {
-        let file = Rc::new(
-            OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .mode(0o600)
-                .open("try-db.db")
-                .expect("Error while opening the file"),
-        );
-        Table {
-            num_rows: 0,
-            pager: Pager::new(file, 0),
-        }
-    }
-    fn open_from_file(file_name: &str) -> Result<Self, Error> {
-        let pager = pager_open(file_name);
-        match pager {
-            Ok(mut pager) => Ok(Table {
-                num_rows: get_num_rows(&mut pager),
-                pager,
-            }),
-            Err(_) => Err(Error::DbOpenError),
+/// CRC-32 (IEEE 802.3) of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
         }
     }
+    !crc
 }
 
-struct Cursor {
-    table: Table,
-    row_num: usize,
-    end_of_table: bool,
+fn stamp_checksum(page: &mut [u8; PAGE_SIZE]) {
+    let checksum = crc32(&page[..PAGE_USABLE_SIZE]);
+    page[PAGE_CHECKSUM_OFFSET..].copy_from_slice(&checksum.to_le_bytes());
 }
 
-impl Cursor {
-    fn new(table: Table) -> Self {
-        Cursor {
-            table,
-            row_num: 0,
-            end_of_table: false,
-        }
-    }
-    fn table_start(&mut self) {
-        self.row_num = 0;
-        self.end_of_table = self.table.num_rows == 0;
+/// Loads a data page on first use. Pages read from disk must be complete and match
+/// their checksum footer; pages past the end of the file start zeroed.
+fn get_page(pager: &mut Pager, page_num: usize) -> Result<&mut [u8; PAGE_SIZE], Error> {
+    if page_num >= TABLE_MAX_PAGES {
+        return Err(Error::PageOutOfBounds);
     }
-    fn table_end(&mut self) {
-        self.row_num = self.table.num_rows;
-        self.end_of_table = true;
-    }
-    fn cursor_advance(&mut self) {
-        self.row_num += 1;
-        if self.row_num >= self.table.num_rows {
-            self.end_of_table = true;
+    if pager.pages[page_num].is_none() {
+        let mut page: Box<[u8; PAGE_SIZE]> = Box::new([0; PAGE_SIZE]);
+        let offset = page_offset(page_num);
+        if offset < pager.file_length {
+            let available = (pager.file_length - offset).min(PAGE_SIZE as u64) as usize;
+            let file = &mut pager.file;
+            file.seek(SeekFrom::Start(offset))
+                .map_err(|_| Error::DbOpenError)?;
+            file.read_exact(&mut page[..available])
+                .map_err(|_| Error::DbOpenError)?;
+            let stored = u32::from_le_bytes(page[PAGE_CHECKSUM_OFFSET..].try_into().unwrap());
+            if available < PAGE_SIZE || stored != crc32(&page[..PAGE_USABLE_SIZE]) {
+                return Err(Error::ChecksumMismatch);
+            }
+            pager.metrics.pages_read += 1;
         }
+        pager.pages[page_num] = Some(page);
     }
+    Ok(pager.pages[page_num].as_mut().unwrap())
+}
 
-    fn cursor_value(&mut self) -> Result<&mut [u8], ExecuteResult> {
-        let row_num = self.row_num;
-        let page_num = row_num / ROWS_PER_PAGE;
-        if page_num > TABLE_MAX_PAGES {
-            return Err(ExecuteTableFull);
-        }
-        let page = get_page(&mut self.table.pager, page_num);
-        match page {
-            Ok(page) => {
-                let row_offset = row_num % ROWS_PER_PAGE;
-                let byte_offset = row_offset * ROW_SIZE;
-                Ok(&mut page[byte_offset..byte_offset + ROW_SIZE])
-            }
-            Err(_err) => Err(ExecuteResult::ExecuteFail),
-        }
+/// Database names are joined onto the db directory, so they must be one plain path
+/// component that can't point outside it. Absolute paths are used as they are.
+fn is_valid_db_name(name: &str) -> bool {
+    if !in_data_dir(name) {
+        return !name.is_empty();
     }
+    name != "." && name != ".." && !name.contains(['/', '\\'])
 }
 
-fn dp_open(filename: &str) -> Result<Table, Error> {
-    Table::open_from_file(filename)
+/// Whether database names are files in the `db` directory, cleared by `--no-subdir`
+/// so names are used as paths verbatim.
+static DB_SUBDIR: AtomicBool = AtomicBool::new(true);
+
+fn set_use_db_subdir(enabled: bool) {
+    DB_SUBDIR.store(enabled, Ordering::Relaxed);
 }
 
-fn db_close(table: &mut Table) {
-    let pager = &mut table.pager;
-    let num_full_pages = table.num_rows / ROWS_PER_PAGE;
-    for i in 0..num_full_pages {
-        if pager.pages[i].is_none() {
-            continue;
-        }
-        pager.pager_flush(i, PAGE_SIZE).expect("Flush Error");
-        pager.pages[i] = None;
+fn use_db_subdir() -> bool {
+    DB_SUBDIR.load(Ordering::Relaxed)
+}
+
+/// The db directory: `TRYDB_DATA_DIR` when it's set and not empty, else `db`.
+fn data_dir() -> PathBuf {
+    env::var_os(DATA_DIR_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map_or_else(|| PathBuf::from(DEFAULT_DATA_DIR), PathBuf::from)
+}
+
+/// Whether `filename` is joined onto the db directory rather than used as a path.
+fn in_data_dir(filename: &str) -> bool {
+    use_db_subdir() && !Path::new(filename).is_absolute()
+}
+
+/// Where the database called `filename` lives on disk.
+fn db_path(filename: &str) -> PathBuf {
+    if in_data_dir(filename) {
+        data_dir().join(filename)
+    } else {
+        PathBuf::from(filename)
     }
-    let additional_rows = table.num_rows % ROWS_PER_PAGE;
-    if additional_rows > 0 {
-        let page_num = num_full_pages;
-        if !pager.pages[page_num].is_none() {
-            pager.pager_flush(page_num, PAGE_SIZE).expect("Flush Error");
-            pager.pages[page_num] = None;
-        }
+}
+
+fn pager_open(filename: &str) -> Result<Pager, Error> {
+    let file_path = db_path(filename);
+    if in_data_dir(filename) {
+        create_db_dir(file_path.parent().unwrap())?;
     }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(file_path)
+        .map_err(|_| Error::DbOpenError)?;
+    let file_length = file
+        .seek(SeekFrom::End(0))
+        .map_err(|_| Error::DbOpenError)?;
+    Ok(Pager::new(Box::new(file), file_length))
 }
 
-fn leaf_node_num_cells(node: &N)
+/// Creates the db directory if it doesn't exist, telling a file in its way apart from
+/// other failures.
+fn create_db_dir(dir: &Path) -> Result<(), Error> {
+    if dir.exists() && !dir.is_dir() {
+        return Err(Error::DbDirNotADirectory);
+    }
+    create_dir_all(dir).map_err(|_| Error::DbOpenError)
+}
 
-fn main() {
-    let mut db_name = String::new();
-    io::stdin().read_line(&mut db_name).unwrap();
-    let table = dp_open(&db_name.trim_end());
-    match table {
-        Ok(mut table) => {
-            let mut cursor: Cursor = Cursor::new(table);
-            loop {
-                let mut input_buffer = InputBuffer::new();
-                read_input(&mut input_buffer);
-                let start = Instant::now();
-                let res = process_input(&mut input_buffer, &mut cursor);
-                let elapsed = start.elapsed();
-                println!("It took {:?}", elapsed);
-                match res {
-                    Ok(_) => {}
-                    Err(Error::MetaCommandError) => {
-                        break;
-                    }
-                    Err(Error::MetaNoCommand) => {
-                        break;
-                    }
-                    Err(Error::MetaCommandExit) => {
-                        break;
+/// Checks the row the header counts last, at the end of the rightmost leaf, and
+/// leaves it out of `num_rows` when it's only partly written. The walk down the right
+/// edge of the tree stops at the first page it can't read as a node.
+fn without_torn_last_row(pager: &mut Pager, schema: Schema, num_rows: usize, fill: u8) -> usize {
+    let layout = schema.layout();
+    let num_pages = pager.num_pages;
+    let file = &mut pager.file;
+    let mut page = vec![0; PAGE_SIZE];
+    let mut page_num = 0;
+    // Each step goes a level down, so more steps than pages means a cycle.
+    for _ in 0..num_pages {
+        if page_num >= num_pages {
+            return num_rows;
+        }
+        if file.seek(SeekFrom::Start(page_offset(page_num))).is_err()
+            || file.read_exact(&mut page).is_err()
+        {
+            return num_rows;
+        }
+        match node_type(&page) {
+            Some(NodeType::NodeInternal) => page_num = internal_node_right_child(&page),
+            Some(NodeType::NodeLeaf) => {
+                let num_cells = leaf_node_num_cells(&page).min(layout.rows_per_page);
+                if num_cells == 0 {
+                    return num_rows;
+                }
+                let last_row = leaf_node_value(&page, layout, num_cells - 1);
+                let kept = without_partial_row(num_rows, last_row, schema, fill);
+                if kept < num_rows {
+                    // Take the dropped cell out of its leaf as well, so inserts don't
+                    // shift it back in. The leaf header is marked dirty so the next
+                    // flush writes it along with the header's count.
+                    if let Ok(page) = get_page(pager, page_num) {
+                        set_leaf_node_num_cells(page, num_cells - 1);
+                        pager.mark_dirty(page_num, 0..LEAF_NODE_HEADER_SIZE);
+                        let _ = adjust_ancestor_rows(pager, page_num, -1);
                     }
-                    _ => {}
                 }
+                return kept;
             }
-            let start = Instant::now();
-            db_close(&mut cursor.table);
-            let elapsed = start.elapsed();
-            println!("It took for closing{:?}", elapsed);
-        }
-        Err(err) => {
-            println!("{:?}", err);
+            None => return num_rows,
         }
     }
+    num_rows
 }
 
-fn process_input(input_buffer: &mut InputBuffer, cursor: &mut Cursor) -> Result<(), Error> {
-    match do_meta_command(&input_buffer) {
-        MetaCommandResult::MetaCommandSuccess => Err(Error::MetaCommandExit),
-        MetaCommandResult::MetaCommandUnrecognizedCommand => Ok(Error::MetaCommandError),
-        MetaCommandResult::MetaNoCommand => {
-            println!("No command is selected");
-            Err(Error::MetaNoCommand)
-        }
-    }?;
-    let mut statement = Statement::new();
-    match prepare_statement(&input_buffer, &mut statement) {
-        PrepareResult::PrepareSuccess => {
-            // println!("Prepare success {:?}", statement);
-            Ok(())
-        }
-        PrepareResult::PrepareUnrecognizedStatement => {
-            println!(
-                "Unrecognized keyword at start of {:?}",
-                &input_buffer.buffer.clone()
-            );
-            Ok(())
-        }
-        PrepareResult::PrepareSyntaxError => {
-            println!("Syntax error: could not parse statement");
-            Err(PrepareError)
-        }
-        PrepareResult::PrepareStringTooLong => Err(PrepareStringTooLong),
-        PrepareResult::PrepareNegativeId => Err(Error::PrepareNegativeId),
-    }?;
-    match execute_statement(&mut statement, cursor) {
-        ExecuteSuccess => {
-            // println!("Query executed successfully");
-            Ok(())
-        }
-        ExecuteResult::ExecuteTableFull => {
-            println!("Insert is not allowed, Table is full");
-            Err(TableFull)
-        }
-        ExecuteResult::ExecuteFail => {
-            println!("Query execution failed");
-            Err(ExecuteError)
+/// Adds `delta` to the row count each ancestor of `page_num` keeps for the child the
+/// page is under, up to the root, marking the counts dirty.
+fn adjust_ancestor_rows(
+    pager: &mut Pager,
+    page_num: usize,
+    delta: isize,
+) -> Result<(), ExecuteResult> {
+    let mut child_page = page_num;
+    // A path up never visits more pages than the table has.
+    for _ in 0..pager.num_pages {
+        let page = get_page(pager, child_page).map_err(|_| ExecuteResult::ExecuteFail)?;
+        if is_node_root(page) {
+            return Ok(());
         }
-    }?;
-    Ok(())
+        let parent_num = node_parent(page);
+        let parent = get_page(pager, parent_num).map_err(|_| ExecuteResult::ExecuteFail)?;
+        let num_keys = internal_node_num_keys(parent).min(INTERNAL_NODE_MAX_CELLS);
+        let child_num = (0..=num_keys)
+            .find(|&child_num| internal_node_child(parent, child_num) == child_page)
+            .ok_or(ExecuteResult::ExecuteFail)?;
+        let rows = internal_node_child_rows(parent, child_num)
+            .checked_add_signed(delta)
+            .ok_or(ExecuteResult::ExecuteFail)?;
+        set_internal_node_child_rows(parent, child_num, rows);
+        let range = internal_node_rows_range(parent, child_num);
+        pager.mark_dirty(parent_num, range);
+        child_page = parent_num;
+    }
+    Err(ExecuteResult::ExecuteFail)
 }
 
-fn print_prompt() {
-    print!("db -> ");
-    io::stdout().flush().unwrap();
+/// Leaves the last of `num_rows` out when it's only partly written.
+fn without_partial_row(num_rows: usize, last_row: &[u8], schema: Schema, fill: u8) -> usize {
+    if num_rows == 0 || !is_partial_row(last_row, schema, fill) {
+        return num_rows;
+    }
+    log!(
+        LogLevel::Error,
+        "Dropped a partially written row at {}",
+        num_rows - 1
+    );
+    num_rows - 1
 }
 
-fn read_input(buffer: &mut InputBuffer) {
-    let mut input = String::new();
-    print_prompt();
-    let n = io::stdin().read_line(&mut input).unwrap();
-    if n == 1 {
-        buffer.buffer = None;
-    } else {
-        buffer.input_length = n as i32 - 1;
-        buffer.buffer = Some(input.trim_end().to_owned());
+/// Whether a stored row that isn't empty was cut off while being written. Users rows
+/// are written id first, then username, then email, and inserts reject empty text,
+/// so a row with an empty username or email never finished. Products have no field
+/// that must be set, so none of them counts as partial.
+fn is_partial_row(row: &[u8], schema: Schema, fill: u8) -> bool {
+    match schema {
+        Schema::Users => {
+            let mut stored = Row::new();
+            deserialize_row(row, &mut stored, fill);
+            stored.username().is_empty() || stored.email().is_empty()
+        }
+        Schema::Products => false,
     }
 }
 
-fn do_meta_command(input_buffer: &InputBuffer) -> MetaCommandResult {
-    if let Some(buffer_data) = &input_buffer.buffer {
-        if buffer_data.eq(".exit") {
-            MetaCommandResult::MetaCommandSuccess
-        } else {
-            MetaCommandResult::MetaCommandUnrecognizedCommand
-        }
-    } else {
-        MetaCommandResult::MetaNoCommand
+/// Pages are always flushed whole, so data that doesn't end on a page boundary was
+/// cut short or written by something else.
+fn is_valid_file_length(file_length: u64) -> bool {
+    let data_length = file_length - HEADER_SIZE as u64;
+    data_length.is_multiple_of(PAGE_SIZE as u64)
+}
+
+/// The key a stored row is filed under: its id, which both schemas keep in their first
+/// four bytes.
+fn row_key(row: &[u8]) -> u32 {
+    u32::from_ne_bytes(row[ID_OFFSET..ID_OFFSET + ID_SIZE].try_into().unwrap())
+}
+
+fn is_empty_row(row: &[u8]) -> bool {
+    row.iter().all(|&b| b == 0)
+}
+
+fn encode_header(schema: Schema, num_rows: usize, fill: u8) -> Box<[u8; HEADER_SIZE]> {
+    let mut header = Box::new([0; HEADER_SIZE]);
+    header[HEADER_MAGIC_OFFSET..HEADER_MAGIC_OFFSET + HEADER_MAGIC.len()]
+        .copy_from_slice(HEADER_MAGIC);
+    header[HEADER_VERSION_OFFSET..HEADER_VERSION_OFFSET + HEADER_VERSION_SIZE]
+        .copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+    header[HEADER_SCHEMA_OFFSET..HEADER_SCHEMA_OFFSET + HEADER_SCHEMA_SIZE]
+        .copy_from_slice(&schema.id().to_le_bytes());
+    header[HEADER_NUM_ROWS_OFFSET..HEADER_NUM_ROWS_OFFSET + HEADER_NUM_ROWS_SIZE]
+        .copy_from_slice(&(num_rows as u32).to_le_bytes());
+    header[HEADER_FILL_BYTE_OFFSET] = fill;
+    header
+}
+
+/// Reads the schema, row count and fill byte out of a header, rejecting files without
+/// the magic or from another format version.
+fn decode_header(header: &[u8; HEADER_SIZE]) -> Result<(Schema, usize, u8), Error> {
+    let read_u32 = |offset: usize| {
+        u32::from_le_bytes(
+            header[offset..offset + size_of::<u32>()]
+                .try_into()
+                .unwrap(),
+        )
+    };
+    if &header[HEADER_MAGIC_OFFSET..HEADER_MAGIC_OFFSET + HEADER_MAGIC.len()] != HEADER_MAGIC
+        || read_u32(HEADER_VERSION_OFFSET) != FORMAT_VERSION
+    {
+        return Err(Error::DbUnsupportedFormat);
     }
+    let schema =
+        Schema::from_id(read_u32(HEADER_SCHEMA_OFFSET)).ok_or(Error::DbUnsupportedFormat)?;
+    let fill = header[HEADER_FILL_BYTE_OFFSET];
+    if !is_valid_fill_byte(fill) {
+        return Err(Error::DbUnsupportedFormat);
+    }
+    Ok((schema, read_u32(HEADER_NUM_ROWS_OFFSET) as usize, fill))
 }
 
-fn prepare_statement(input_buffer: &InputBuffer, statement: &mut Statement) -> PrepareResult {
-    if let Some(buffer_data) = &input_buffer.buffer {
-        return match &buffer_data[..6] {
-            "insert" => {
-                statement.statement_type = Some(StatementType::StatementInsert);
-                match scan_fmt!(buffer_data, "insert {} {} {}", i32, String, String) {
-                    Ok((id, name, email)) => {
-                        if id < 0 {
-                            return PrepareResult::PrepareNegativeId;
-                        }
-                        if email.len() > EMAIL_SIZE || name.len() > USERNAME_SIZE {
-                            return PrepareResult::PrepareStringTooLong;
-                        }
-                        statement.row_to_insert.id = id;
-                        statement.row_to_insert.email = email;
-                        statement.row_to_insert.username = name;
-                        PrepareResult::PrepareSuccess
-                    }
-                    Err(_) => PrepareResult::PrepareSyntaxError,
+impl Table {
+    /// Opens a file that must hold `schema`, creating it with that schema if it's new.
+    fn open_with_schema(file_name: &str, schema: Schema) -> Result<Self, Error> {
+        TableConfig::new().schema(schema).open(file_name)
+    }
+    fn open(file_name: &str, expected: Option<Schema>) -> Result<Self, Error> {
+        if !is_valid_db_name(file_name) {
+            return Err(Error::InvalidDbName);
+        }
+        let pager = pager_open(file_name)?;
+        Table::load(pager, expected, Some(file_name.to_owned()))
+    }
+    /// Loads a whole db image into a table kept in memory; an empty slice makes a new
+    /// users table.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let pager = Pager::new(
+            Box::new(io::Cursor::new(bytes.to_vec())),
+            bytes.len() as u64,
+        );
+        Table::load(pager, None, None)
+    }
+    /// The image `db_close` would leave on disk: the header, then every used page with
+    /// its checksum. Nothing is written to the backing storage.
+    // Needs `&mut self` because pages that aren't resident yet are read in.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_bytes(&mut self) -> Result<Vec<u8>, Error> {
+        let mut image = encode_header(self.schema, self.num_rows, self.fill).to_vec();
+        for page_num in 0..self.page_count() {
+            let mut page = *get_page(&mut self.pager, page_num)?;
+            stamp_checksum(&mut page);
+            image.extend_from_slice(&page);
+        }
+        Ok(image)
+    }
+    /// Reads the header and rows behind `pager`, writing a fresh header if it's empty.
+    fn load(
+        mut pager: Pager,
+        expected: Option<Schema>,
+        file_name: Option<String>,
+    ) -> Result<Self, Error> {
+        if pager.file_length == 0 {
+            let schema = expected.unwrap_or(Schema::Users);
+            let fill = fill_byte();
+            pager
+                .write_header(&encode_header(schema, 0, fill))
+                .map_err(|_| Error::DbOpenError)?;
+            return Ok(Table {
+                num_rows: 0,
+                schema,
+                layout: schema.layout(),
+                max_rows: schema.layout().max_rows,
+                append_only: false,
+                read_only: false,
+                pager,
+                file_name,
+                row_cache: RowCache::new(ROW_CACHE_CAPACITY),
+                fill,
+            });
+        }
+        if pager.file_length < HEADER_SIZE as u64 {
+            return Err(Error::DbCorruptFile);
+        }
+        let header = pager.read_header().map_err(|_| Error::DbOpenError)?;
+        let (schema, num_rows, fill) = decode_header(&header)?;
+        if expected.is_some_and(|expected| expected != schema) {
+            return Err(Error::SchemaMismatch);
+        }
+        let layout = schema.layout();
+        if !is_valid_file_length(pager.file_length) || num_rows > layout.max_rows {
+            return Err(Error::DbCorruptFile);
+        }
+        Ok(Table {
+            num_rows: without_torn_last_row(&mut pager, schema, num_rows, fill),
+            schema,
+            layout,
+            max_rows: layout.max_rows,
+            append_only: false,
+            read_only: false,
+            pager,
+            file_name,
+            row_cache: RowCache::new(ROW_CACHE_CAPACITY),
+            fill,
+        })
+    }
+    /// Leaf pages in key order, found by walking the tree down from the root.
+    fn leaf_pages(&mut self) -> Result<Vec<usize>, ExecuteResult> {
+        if self.page_count() == 0 {
+            return Ok(Vec::new());
+        }
+        self.leaves_under(0)
+    }
+    /// The leaves of the subtree rooted at `page_num`, in key order.
+    fn leaves_under(&mut self, page_num: usize) -> Result<Vec<usize>, ExecuteResult> {
+        let mut leaves = Vec::new();
+        // Children are pushed last to first, so leaves come off the stack in key order.
+        let mut pending = vec![page_num];
+        let mut visited = 0;
+        while let Some(page_num) = pending.pop() {
+            visited += 1;
+            if visited > self.page_count() {
+                return Err(ExecuteResult::ExecuteFail);
+            }
+            let page =
+                get_page(&mut self.pager, page_num).map_err(|_| ExecuteResult::ExecuteFail)?;
+            match node_type(page) {
+                Some(NodeType::NodeLeaf) => leaves.push(page_num),
+                Some(NodeType::NodeInternal) => {
+                    let num_keys = internal_node_num_keys(page).min(INTERNAL_NODE_MAX_CELLS);
+                    pending.extend(
+                        (0..=num_keys)
+                            .rev()
+                            .map(|child| internal_node_child(page, child)),
+                    );
                 }
+                None => return Err(ExecuteResult::ExecuteFail),
             }
-            "select" => {
-                if buffer_data.len() > 6 {
-                    match scan_fmt!(buffer_data, "select {} ", String) {
-                        Ok(email) => {
-                            statement.row_to_insert.email = email;
-                            statement.statement_type =
-                                Some(StatementType::StatementSelectWithEmail);
+        }
+        Ok(leaves)
+    }
+    /// The leaf holding `row_num` and the row's cell within it, counting rows in key
+    /// order. Each internal node on the way down says how many rows its children hold,
+    /// so only the pages on the path are read.
+    fn row_location(&mut self, row_num: usize) -> Result<(usize, usize), ExecuteResult> {
+        let (mut page_num, mut row_num) = (0, row_num);
+        // A path down never visits more pages than the table has.
+        for _ in 0..self.page_count() {
+            let page =
+                get_page(&mut self.pager, page_num).map_err(|_| ExecuteResult::ExecuteFail)?;
+            match node_type(page) {
+                Some(NodeType::NodeLeaf) if row_num < leaf_node_num_cells(page) => {
+                    return Ok((page_num, row_num));
+                }
+                Some(NodeType::NodeInternal) => {
+                    let num_keys = internal_node_num_keys(page).min(INTERNAL_NODE_MAX_CELLS);
+                    let mut child = None;
+                    for child_num in 0..=num_keys {
+                        let rows = internal_node_child_rows(page, child_num);
+                        if row_num < rows {
+                            child = Some(internal_node_child(page, child_num));
+                            break;
                         }
-                        Err(_) => {}
+                        row_num -= rows;
                     }
-                } else {
-                    statement.statement_type = Some(StatementType::StatementSelect);
+                    page_num = child.ok_or(ExecuteResult::ExecuteFail)?;
                 }
-                PrepareResult::PrepareSuccess
+                _ => break,
             }
-            _ => PrepareResult::PrepareUnrecognizedStatement,
+        }
+        Err(ExecuteResult::ExecuteFail)
+    }
+    /// The row in `row_num`'s cell. The cell must be one its leaf's header counts.
+    fn row_slot(&mut self, row_num: usize) -> Result<&mut [u8], ExecuteResult> {
+        let (page_num, cell_num) = self.row_location(row_num)?;
+        let layout = self.layout;
+        let page = get_page(&mut self.pager, page_num).map_err(|_| ExecuteResult::ExecuteFail)?;
+        Ok(leaf_node_value_mut(page, layout, cell_num))
+    }
+    /// The row in `row_num`'s cell, refiled under `key`, for overwriting. The key must
+    /// keep the row's place in key order.
+    fn cell_slot(&mut self, row_num: usize, key: u32) -> Result<&mut [u8], ExecuteResult> {
+        let (page_num, cell_num) = self.row_location(row_num)?;
+        let layout = self.layout;
+        let page = get_page(&mut self.pager, page_num).map_err(|_| ExecuteResult::ExecuteFail)?;
+        set_leaf_node_key(page, layout, cell_num, key);
+        self.pager
+            .mark_dirty(page_num, leaf_node_cell_range(layout, cell_num));
+        let page = self.pager.pages[page_num].as_mut().unwrap();
+        Ok(leaf_node_value_mut(&mut page[..], layout, cell_num))
+    }
+    /// Reads a users row, from the row cache when it was read recently.
+    fn read_row(&mut self, row_num: usize) -> Result<Row, ExecuteResult> {
+        if let Some(row) = self.row_cache.get(row_num) {
+            return Ok(row);
+        }
+        let mut row = Row::new();
+        let fill = self.fill;
+        deserialize_row(self.row_slot(row_num)?, &mut row, fill);
+        self.row_cache.insert(row_num, row.clone());
+        Ok(row)
+    }
+    fn write_row(&mut self, row_num: usize, row: &Row) -> Result<(), ExecuteResult> {
+        self.row_cache.invalidate(row_num);
+        let fill = self.fill;
+        serialize_row(row, self.cell_slot(row_num, row.id())?, fill);
+        Ok(())
+    }
+    /// Removes `row_num`'s cell from its leaf, moving the rows after it in that leaf
+    /// down. A leaf left empty stays in the tree.
+    fn delete_row(&mut self, row_num: usize) -> Result<(), ExecuteResult> {
+        let (page_num, cell_num) = self.row_location(row_num)?;
+        let layout = self.layout;
+        let page = get_page(&mut self.pager, page_num).map_err(|_| ExecuteResult::ExecuteFail)?;
+        let num_cells = leaf_node_num_cells(page);
+        leaf_node_delete(page, layout, cell_num);
+        self.pager
+            .mark_dirty(page_num, 0..layout.cell_offset(num_cells));
+        adjust_ancestor_rows(&mut self.pager, page_num, -1)?;
+        // Every row after the deleted one moved down a row number.
+        self.row_cache.clear();
+        self.num_rows -= 1;
+        Ok(())
+    }
+    /// Reads a users row as its stored bytes, past the row cache.
+    fn read_raw_row(&mut self, row_num: usize) -> Result<RawRow, ExecuteResult> {
+        let fill = self.fill;
+        Ok(deserialize_raw_row(self.row_slot(row_num)?, fill))
+    }
+    /// Where `key` is stored, or would be: the leaf reached by descending from the
+    /// root, binary searching each internal node for the child to follow, then the first
+    /// cell there whose key isn't smaller, and that cell's row number. The rows under
+    /// the children passed over are read from each internal node's counts. The table
+    /// must have a root.
+    fn find_key(&mut self, key: u32) -> Result<(usize, usize, usize), ExecuteResult> {
+        let layout = self.layout;
+        let (mut page_num, mut first_row) = (0, 0);
+        // A path down never visits more pages than the table has.
+        for _ in 0..self.page_count() {
+            let page =
+                get_page(&mut self.pager, page_num).map_err(|_| ExecuteResult::ExecuteFail)?;
+            match node_type(page) {
+                Some(NodeType::NodeLeaf) => {
+                    let cell_num = leaf_node_find(page, layout, key);
+                    return Ok((page_num, cell_num, first_row + cell_num));
+                }
+                Some(NodeType::NodeInternal) => {
+                    let child_num = internal_node_find_child(page, key);
+                    // Rows under the children to the left come before any under this one.
+                    first_row += (0..child_num)
+                        .map(|left| internal_node_child_rows(page, left))
+                        .sum::<usize>();
+                    page_num = internal_node_child(page, child_num);
+                }
+                None => break,
+            }
+        }
+        Err(ExecuteResult::ExecuteFail)
+    }
+    /// Where `key` would be filed before any split: its leaf, its cell there, and
+    /// whether that leaf is full. An empty table would start its root leaf.
+    fn insert_target(&mut self, key: u32) -> Result<(usize, usize, bool), ExecuteResult> {
+        if self.page_count() == 0 {
+            return Ok((0, 0, true));
+        }
+        let (page_num, cell_num, _) = self.find_key(key)?;
+        let page = get_page(&mut self.pager, page_num).map_err(|_| ExecuteResult::ExecuteFail)?;
+        let is_full = leaf_node_num_cells(page) >= self.layout.rows_per_page;
+        Ok((page_num, cell_num, is_full))
+    }
+    /// Files a new row under `key` in key order and returns the row's bytes to fill in.
+    /// The row goes into the leaf the key belongs in, shifting the cells after it up,
+    /// and a full leaf is split to make room. A key that's already stored is refused.
+    fn insert_cell(&mut self, key: u32) -> Result<&mut [u8], ExecuteResult> {
+        let layout = self.layout;
+        if self.page_count() == 0 {
+            let page_num = self.pager.allocate_page().ok_or(ExecuteTableFull)?;
+            initialize_leaf_node(&mut self.pager.pages[page_num].as_mut().unwrap()[..], true);
+        }
+        let (page_num, cell_num, is_full) = self.insert_target(key)?;
+        let page = get_page(&mut self.pager, page_num).map_err(|_| ExecuteResult::ExecuteFail)?;
+        let num_cells = leaf_node_num_cells(page);
+        if cell_num < num_cells && leaf_node_key(page, layout, cell_num) == key {
+            return Err(ExecuteResult::ExecuteDuplicateKey);
+        }
+        let (page_num, cell_num) = if !is_full {
+            leaf_node_insert(page, layout, cell_num, key);
+            self.pager
+                .mark_dirty(page_num, 0..layout.cell_offset(num_cells + 1));
+            adjust_ancestor_rows(&mut self.pager, page_num, 1)?;
+            (page_num, cell_num)
+        } else {
+            self.split_leaf_and_insert(page_num, cell_num, key)?
         };
+        // Every row after the new one moved up a row number.
+        self.row_cache.clear();
+        self.num_rows += 1;
+        let page = self.pager.pages[page_num].as_mut().unwrap();
+        Ok(leaf_node_value_mut(&mut page[..], layout, cell_num))
     }
-    PrepareResult::PrepareUnrecognizedStatement
-}
+    /// Splits the full leaf `page_num` to fit `key` in at `cell_num`: the upper half of
+    /// its cells, counting the new one, moves to a new leaf. A root leaf becomes the left
+    /// child of a new internal root; any other leaf adds the new one to its parent.
+    /// Returns the new cell's leaf and cell number, with only its key written.
+    fn split_leaf_and_insert(
+        &mut self,
+        page_num: usize,
+        cell_num: usize,
+        key: u32,
+    ) -> Result<(usize, usize), ExecuteResult> {
+        let layout = self.layout;
+        let old = *get_page(&mut self.pager, page_num).map_err(|_| ExecuteResult::ExecuteFail)?;
+        let is_root = is_node_root(&old);
+        let parent_num = node_parent(&old);
+        // Splitting the root takes a page for its left child as well. Nothing is changed
+        // until the split is known to fit.
+        let pages_needed = if is_root { 2 } else { 1 };
+        if self.page_count() + pages_needed > TABLE_MAX_PAGES {
+            return Err(ExecuteTableFull);
+        }
+        if !is_root {
+            let parent =
+                get_page(&mut self.pager, parent_num).map_err(|_| ExecuteResult::ExecuteFail)?;
+            if internal_node_num_keys(parent) >= INTERNAL_NODE_MAX_CELLS {
+                return Err(ExecuteTableFull);
+            }
+        }
+        let new_page_num = self.pager.allocate_page().ok_or(ExecuteTableFull)?;
+        let left_count = layout.left_split_count();
+        let new_page = &mut self.pager.pages[new_page_num].as_mut().unwrap()[..];
+        initialize_leaf_node(new_page, false);
+        set_node_parent(new_page, parent_num);
+        set_leaf_node_num_cells(new_page, layout.rows_per_page + 1 - left_count);
 
-fn execute_statement(statement: &Statement, cursor: &mut Cursor) -> ExecuteResult {
-    return match &statement.statement_type {
-        None => {
-            println!("The statement is not valid for execution");
-            ExecuteResult::ExecuteFail
+        let mut inserted = (page_num, cell_num);
+        for position in 0..=layout.rows_per_page {
+            let (target_num, target_cell) = if position < left_count {
+                (page_num, position)
+            } else {
+                (new_page_num, position - left_count)
+            };
+            let target = &mut self.pager.pages[target_num].as_mut().unwrap()[..];
+            if position == cell_num {
+                set_leaf_node_key(target, layout, target_cell, key);
+                inserted = (target_num, target_cell);
+            } else {
+                let source = if position < cell_num {
+                    position
+                } else {
+                    position - 1
+                };
+                target[leaf_node_cell_range(layout, target_cell)]
+                    .copy_from_slice(&old[leaf_node_cell_range(layout, source)]);
+            }
         }
-        Some(stmt) => match stmt {
-            StatementType::StatementInsert => execute_insert(statement, cursor),
-            StatementType::StatementSelect => execute_select(statement, cursor),
-            StatementType::StatementSelectWithEmail => {
-                execute_select_with_email(&statement.row_to_insert.email, cursor)
+        let old_page = &mut self.pager.pages[page_num].as_mut().unwrap()[..];
+        set_leaf_node_num_cells(old_page, left_count);
+        // The cells that moved are cleared, so only the new leaf holds them.
+        old_page[layout.cell_offset(left_count)..PAGE_USABLE_SIZE].fill(0);
+        let old_max_key = leaf_node_key(old_page, layout, left_count - 1);
+        self.pager.mark_dirty(page_num, 0..PAGE_USABLE_SIZE);
+
+        if is_root {
+            let left_num = self.create_new_root(new_page_num)?;
+            if inserted.0 == page_num {
+                inserted.0 = left_num;
             }
-        },
-    };
-}
+        } else {
+            let parent =
+                get_page(&mut self.pager, parent_num).map_err(|_| ExecuteResult::ExecuteFail)?;
+            let num_keys = internal_node_num_keys(parent);
+            let child_num = (0..=num_keys)
+                .find(|&child_num| internal_node_child(parent, child_num) == page_num)
+                .ok_or(ExecuteResult::ExecuteFail)?;
+            // The old leaf keeps its slot under its new, smaller largest key, and the new
+            // leaf takes the slot after it, which was the old leaf's.
+            internal_node_insert(parent, child_num, page_num, old_max_key, left_count);
+            set_internal_node_child(parent, child_num + 1, new_page_num);
+            set_internal_node_child_rows(
+                parent,
+                child_num + 1,
+                layout.rows_per_page + 1 - left_count,
+            );
+            self.pager.mark_dirty(parent_num, 0..PAGE_USABLE_SIZE);
+            adjust_ancestor_rows(&mut self.pager, parent_num, 1)?;
+        }
+        Ok(inserted)
+    }
+    /// Moves the root leaf, already split, to a new page and turns page 0 into an
+    /// internal root over it and `right_num`. Returns the page the old root moved to.
+    fn create_new_root(&mut self, right_num: usize) -> Result<usize, ExecuteResult> {
+        let layout = self.layout;
+        let left_num = self.pager.allocate_page().ok_or(ExecuteTableFull)?;
+        let root = *get_page(&mut self.pager, 0).map_err(|_| ExecuteResult::ExecuteFail)?;
+        let left = self.pager.pages[left_num].as_mut().unwrap();
+        **left = root;
+        set_node_root(&mut left[..], false);
+        set_node_parent(&mut left[..], 0);
+        let left_rows = leaf_node_num_cells(&left[..]);
+        let left_max_key = leaf_node_key(&left[..], layout, left_rows - 1);
+        let right = get_page(&mut self.pager, right_num).map_err(|_| ExecuteResult::ExecuteFail)?;
+        let right_rows = leaf_node_num_cells(right);
 
-fn execute_insert(statement: &Statement, cursor: &mut Cursor) -> ExecuteResult {
-    if cursor.table.num_rows >= TABLE_MAX_ROWS {
-        return ExecuteTableFull;
+        let root = get_page(&mut self.pager, 0).map_err(|_| ExecuteResult::ExecuteFail)?;
+        root[..PAGE_USABLE_SIZE].fill(0);
+        initialize_internal_node(root, true);
+        internal_node_insert(root, 0, left_num, left_max_key, left_rows);
+        set_internal_node_right_child(root, right_num);
+        set_internal_node_child_rows(root, 1, right_rows);
+        self.pager.mark_dirty(0, 0..PAGE_USABLE_SIZE);
+        Ok(left_num)
     }
-    serialize_row(&statement.row_to_insert, cursor.cursor_value().unwrap());
-    cursor.table.num_rows += 1;
-    cursor.cursor_advance();
-    ExecuteSuccess
-}
-fn execute_select_with_email(email: &String, cursor: &mut Cursor) -> ExecuteResult {
-    let mut row = Row::new();
-    let mut i = 0;
-    let start = Instant::now();
-    cursor.table_start();
-    while !cursor.end_of_table {
-        deserialize_row(cursor.cursor_value().unwrap(), &mut row);
-        if row.email.eq(email) {
-            println!("Found the row {:?} \n at index {}", row, i);
-            break;
+    fn insert_row(&mut self, row: &Row) -> Result<(), ExecuteResult> {
+        let fill = self.fill;
+        serialize_row(row, self.insert_cell(row.id())?, fill);
+        Ok(())
+    }
+    fn insert_raw_row(&mut self, row: &RawRow) -> Result<(), ExecuteResult> {
+        let fill = self.fill;
+        serialize_raw_row(row, self.insert_cell(row.id())?, fill);
+        Ok(())
+    }
+    fn insert_product(&mut self, product: &Product) -> Result<(), ExecuteResult> {
+        serialize_product(product, self.insert_cell(product.id as u32)?);
+        Ok(())
+    }
+    /// Caps the table at `max_rows`, which can't exceed what the layout fits.
+    fn with_max_rows(mut self, max_rows: usize) -> Self {
+        self.max_rows = max_rows.min(self.layout.max_rows);
+        self
+    }
+    fn with_append_only(mut self, append_only: bool) -> Self {
+        self.append_only = append_only;
+        self
+    }
+    fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+    /// Validates and inserts rows without going through the statement parser.
+    /// Stops early once the table is full and returns how many rows were written.
+    pub fn bulk_insert(&mut self, rows: impl Iterator<Item = Row>) -> Result<usize, Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
         }
-        cursor.cursor_advance();
-        i += 1;
+        if self.schema != Schema::Users {
+            return Err(Error::SchemaMismatch);
+        }
+        let mut inserted = 0;
+        for row in rows {
+            if self.num_rows >= self.max_rows {
+                break;
+            }
+            match validate_row(&row) {
+                PrepareResult::PrepareSuccess => {}
+                PrepareResult::PrepareNegativeId => return Err(Error::PrepareNegativeId),
+                PrepareResult::PrepareStringTooLong => return Err(PrepareStringTooLong),
+                PrepareResult::PrepareEmptyValue => return Err(Error::PrepareEmptyValue),
+                _ => return Err(PrepareError),
+            }
+            match self.insert_row(&row) {
+                Ok(()) => inserted += 1,
+                Err(ExecuteTableFull) => break,
+                Err(ExecuteResult::ExecuteDuplicateKey) => return Err(Error::DuplicateKey),
+                Err(_) => return Err(ExecuteError),
+            }
+        }
+        Ok(inserted)
     }
-    let elapsed = start.elapsed();
-    println!("It took {:?} to complete the select with email", elapsed);
-    ExecuteSuccess
-}
-fn execute_select(_: &Statement, cursor: &mut Cursor) -> ExecuteResult {
-    let mut row = Row::new();
-    let mut i = 0;
-    cursor.table_start();
-    while !cursor.end_of_table {
-        deserialize_row(cursor.cursor_value().unwrap(), &mut row);
-        cursor.cursor_advance();
-        println!("Row {} {:?}", i, row);
-        i += 1;
-    }
-    ExecuteSuccess
-}
-
-fn serialize_row(source: &Row, destination: &mut [u8]) {
-    unsafe {
-        ptr::copy_nonoverlapping(
-            &source.id as *const i32 as *const u8,
-            destination.as_mut_ptr().add(ID_OFFSET),
-            ID_SIZE,
-        );
-        let username_bytes = source.username.as_bytes();
-        ptr::copy_nonoverlapping(
-            username_bytes.as_ptr(),
-            destination.as_mut_ptr().add(USERNAME_OFFSET),
-            USERNAME_SIZE,
-        );
-        let email_bytes = source.email.as_bytes();
-        let email_length = email_bytes.len().min(EMAIL_SIZE);
-        ptr::copy_nonoverlapping(
-            email_bytes.as_ptr(),
-            destination.as_mut_ptr().add(EMAIL_OFFSET),
-            email_length,
-        );
-        if email_length < EMAIL_SIZE {
-            ptr::write_bytes(
-                destination.as_mut_ptr().add(EMAIL_OFFSET + email_length),
-                0,
-                EMAIL_SIZE - email_length,
-            );
+    /// Rows that can still be inserted before the table is full.
+    pub fn free_rows(&self) -> usize {
+        self.max_rows.saturating_sub(self.num_rows)
+    }
+    /// Bytes the header and the stored rows take, leaving out page slack and footers.
+    pub fn bytes_used(&self) -> u64 {
+        HEADER_SIZE as u64 + self.num_rows as u64 * self.layout.row_size as u64
+    }
+    /// Bytes the backing file takes.
+    pub fn bytes_allocated(&self) -> u64 {
+        self.pager.file_length
+    }
+    fn page_count(&self) -> usize {
+        self.pager.num_pages
+    }
+    /// Counts rows from the headers of the tree's leaves, ignoring `num_rows`.
+    fn count_stored_rows(&mut self) -> Result<usize, ExecuteResult> {
+        let mut stored = 0;
+        for page_num in self.leaf_pages()? {
+            let page =
+                get_page(&mut self.pager, page_num).map_err(|_| ExecuteResult::ExecuteFail)?;
+            stored += leaf_node_num_cells(page);
+        }
+        Ok(stored)
+    }
+    /// Releases the pages past the ones the tree uses, in memory and on disk, and
+    /// returns how many were resident.
+    fn shrink(&mut self) -> io::Result<usize> {
+        self.row_cache.clear();
+        let released = self.pager.shrink_to(self.page_count());
+        self.pager.truncate_to_length()?;
+        Ok(released)
+    }
+    /// Removes every row and returns how many there were.
+    fn clear(&mut self) -> Result<usize, Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
         }
+        if self.append_only {
+            return Err(Error::AppendOnly);
+        }
+        let cleared = self.num_rows;
+        self.num_rows = 0;
+        self.pager.discard_pages();
+        self.row_cache.clear();
+        Ok(cleared)
     }
 }
 
-fn deserialize_row(source: &[u8], destination: &mut Row) {
-    unsafe {
-        ptr::copy_nonoverlapping(
-            source.as_ptr().add(ID_OFFSET),
-            &mut destination.id as *mut i32 as *mut u8,
-            ID_SIZE,
-        );
+/// A position in a table. Each statement builds its own cursor, so no walk leaves
+/// state behind for the next one.
+struct Cursor<'a> {
+    table: &'a mut Table,
+    row_num: usize,
+    end_of_table: bool,
+}
 
-        let username_bytes = &source[USERNAME_OFFSET..USERNAME_OFFSET + USERNAME_SIZE];
-        destination.username = String::from_utf8_lossy(username_bytes)
-            .trim_end_matches('\0')
-            .to_string();
+impl<'a> Cursor<'a> {
+    fn table_start(table: &'a mut Table) -> Self {
+        let end_of_table = table.num_rows == 0;
+        Cursor {
+            table,
+            row_num: 0,
+            end_of_table,
+        }
+    }
+    /// A cursor on `row_num`; at or past `num_rows` it is already at the end.
+    fn at_row(table: &'a mut Table, row_num: usize) -> Self {
+        let end_of_table = row_num >= table.num_rows;
+        Cursor {
+            table,
+            row_num,
+            end_of_table,
+        }
+    }
+    /// A cursor on the row filed under `key`, or where it would go when there's none:
+    /// on the first row with a larger key, or at the end.
+    fn find(table: &'a mut Table, key: u32) -> Result<Self, ExecuteResult> {
+        let row_num = match table.page_count() {
+            0 => 0,
+            _ => table.find_key(key)?.2,
+        };
+        Ok(Cursor::at_row(table, row_num))
+    }
+    fn cursor_advance(&mut self) {
+        self.row_num += 1;
+        if self.row_num >= self.table.num_rows {
+            self.end_of_table = true;
+        }
+    }
 
-        let email_bytes = &source[EMAIL_OFFSET..EMAIL_OFFSET + EMAIL_SIZE];
-        destination.email = String::from_utf8_lossy(email_bytes)
-            .trim_end_matches('\0')
-            .to_string();
+    fn cursor_value(&mut self) -> Result<&mut [u8], ExecuteResult> {
+        self.table.row_slot(self.row_num)
+    }
+    /// The users row under the cursor, going through the table's row cache.
+    fn cursor_row(&mut self) -> Result<Row, ExecuteResult> {
+        self.table.read_row(self.row_num)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::{process_input, Cursor, Error, InputBuffer, Table};
+/// Opens `filename` inside the db directory, `db` unless `TRYDB_DATA_DIR` names
+/// another, with the options set in `config`. A file that doesn't exist yet is
+/// created, holding an empty table of the configured schema or users.
+///
+/// Returns `Error::InvalidDbName` when `filename` isn't a single path component,
+/// `Error::DbDirNotADirectory` when a file is in the way of the db directory,
+/// `Error::DbOpenError` when the file can't be created or read,
+/// `Error::DbCorruptFile` when its length doesn't fit the layout,
+/// `Error::DbUnsupportedFormat` when it has no readable header, and
+/// `Error::SchemaMismatch` when it holds another schema than the configured one.
+fn db_open(filename: &str, config: &TableConfig) -> Result<Table, Error> {
+    config.open(filename)
+}
 
-    #[test]
-    fn test_inserting_and_retrieving_a_row() {
-        let table = Table::new();
-        let mut cursor = Cursor::new(table);
-        let mut input_buffer = InputBuffer::new();
-        let str = String::from("insert 1 bala bala@gmail.com");
-        input_buffer.buffer_length = str.len() as i32;
-        input_buffer.buffer = Some(str);
-        let _ = process_input(&mut input_buffer, &mut cursor);
-        assert_eq!(cursor.table.num_rows, 1);
+/// Flushes and drops every page, returning the pages that couldn't be written. Their
+/// changes are lost, but every other page still reaches the file. Failing to write the
+/// header, to truncate or to sync the file is an error for the whole file.
+///
+/// Every page of the tree is flushed by the same pass over `0..page_count()`, and a
+/// page stops being dirty once written, so no page is written twice however the rows
+/// divide into pages.
+fn db_close(table: &mut Table) -> io::Result<Vec<(usize, io::Error)>> {
+    let num_pages = table.page_count();
+    let header = encode_header(table.schema, table.num_rows, table.fill);
+    let read_only = table.read_only;
+    let pager = &mut table.pager;
+    let failures = pager.flush_dirty_pages(0..num_pages);
+    debug_assert_eq!(
+        pager.dirty[..num_pages].iter().flatten().count(),
+        failures.len()
+    );
+    pager.shrink_to(num_pages);
+    pager.pages[..num_pages].fill(None);
+    // The row count goes in after the pages it counts.
+    if !read_only {
+        pager.write_header(&header)?;
+    }
+    pager.truncate_to_length()?;
+    pager.sync_if_durable()?;
+    Ok(failures)
+}
+
+/// Writes every dirty page and then the row count in the header, keeping the pages
+/// resident, so a file that is never closed still opens with every row saved so far.
+/// The header is left alone when a page fails, so it never counts rows the file lacks.
+fn db_autosave(table: &mut Table) -> io::Result<Vec<(usize, io::Error)>> {
+    let num_pages = table.page_count();
+    let header = encode_header(table.schema, table.num_rows, table.fill);
+    let pager = &mut table.pager;
+    let failures = pager.flush_dirty_pages(0..num_pages);
+    if failures.is_empty() && !table.read_only {
+        pager.write_header(&header)?;
     }
+    pager.sync_if_durable()?;
+    Ok(failures)
+}
 
-    #[test]
-    fn test_table_full() {
-        let table = Table::new();
-        let mut input_buffer = InputBuffer::new();
-        let mut cursor = Cursor::new(table);
-        for i in 0..1400 {
-            let str = format!("insert {} bala bala@gmail.com", i);
-            input_buffer.buffer_length = str.len() as i32;
-            input_buffer.buffer = Some(str);
-            let _ = process_input(&mut input_buffer, &mut cursor);
+/// Rebuilds `file_name` from its rows alone: every page that isn't an internal node is
+/// read for rows up to its first empty cell, partly written rows are dropped, and the
+/// rest are filed again in a fresh tree behind a new `schema` header, with new node
+/// headers and checksums. A file is read past its header, whatever the header holds,
+/// as long as it still starts with the magic. Returns the number of rows kept.
+fn recover_file(file_name: &str, schema: Schema) -> Result<usize, Error> {
+    if !is_valid_db_name(file_name) {
+        return Err(Error::InvalidDbName);
+    }
+    let path = db_path(file_name);
+    let bytes = fs::read(&path).map_err(|_| Error::DbOpenError)?;
+    let data = if bytes.starts_with(HEADER_MAGIC) {
+        &bytes[HEADER_SIZE.min(bytes.len())..]
+    } else {
+        &bytes[..]
+    };
+    // The header's fill byte is trusted as long as it could be one.
+    let fill = match bytes.get(HEADER_FILL_BYTE_OFFSET) {
+        Some(&fill) if bytes.starts_with(HEADER_MAGIC) && is_valid_fill_byte(fill) => fill,
+        _ => fill_byte(),
+    };
+    let layout = schema.layout();
+    let mut rows = Vec::new();
+    for page in data.chunks(PAGE_SIZE) {
+        if node_type(page) == Some(NodeType::NodeInternal) {
+            continue;
         }
-        let res = process_input(&mut input_buffer, &mut cursor);
-        assert!(matches!(res, Err(Error::TableFull)));
+        for cell_num in 0..layout.rows_per_page {
+            let start = layout.value_offset(cell_num);
+            match page.get(start..start + layout.row_size) {
+                Some(row) if !is_empty_row(row) => rows.push(row),
+                _ => break,
+            }
+        }
+    }
+    rows.retain(|row| {
+        let partial = is_partial_row(row, schema, fill);
+        if partial {
+            log!(
+                LogLevel::Error,
+                "Dropped a partially written row with id {}",
+                row_key(row)
+            );
+        }
+        !partial
+    });
+    rows.sort_by_key(|row| row_key(row));
+    rows.dedup_by_key(|row| row_key(row));
+    let mut table = Table::from_bytes(&encode_header(schema, 0, fill)[..])?;
+    for row in &rows {
+        table
+            .insert_cell(row_key(row))
+            .map_err(|_| Error::TableFull)?
+            .copy_from_slice(row);
     }
+    let image = table.to_bytes()?;
+    write_atomically(&path, &image).map_err(|_| Error::DbOpenError)?;
+    Ok(rows.len())
+}
 
-    #[test]
-    fn allows_inserting_strings_with_maximum_length() {
-        let long_username = "a".repeat(33);
-        let long_email = "a".repeat(255);
-        let table = Table::new();
-        let mut cursor = Cursor::new(table);
-        let mut input_buffer = InputBuffer::new();
-        let str = format!("insert 1 {} {}", long_username, long_email);
-        input_buffer.buffer_length = str.len() as i32;
-        input_buffer.buffer = Some(str);
-        let res = process_input(&mut input_buffer, &mut cursor);
-        assert!(matches!(res, Err(Error::PrepareStringTooLong)));
+fn report_flush_failures(failures: &[(usize, io::Error)], out: &mut impl Write) {
+    outln!(out, "Failed to flush {} pages:", failures.len());
+    for (page_num, err) in failures {
+        outln!(out, "  page {}: {}", page_num, err);
     }
+}
 
-    #[test]
+/// Prints whatever `db_close` failed to write, returning whether it all reached the file.
+fn report_close_failures(
+    closed: io::Result<Vec<(usize, io::Error)>>,
+    out: &mut impl Write,
+) -> bool {
+    match closed {
+        Ok(failures) if failures.is_empty() => true,
+        Ok(failures) => {
+            report_flush_failures(&failures, out);
+            false
+        }
+        Err(err) => {
+            outln!(out, "Failed to close the db: {}", err);
+            false
+        }
+    }
+}
+
+/// Whether `since_last` has reached the `--autosave-secs` interval; an interval of 0
+/// never autosaves.
+fn autosave_due(interval_secs: u64, since_last: Duration) -> bool {
+    interval_secs != 0 && since_last >= Duration::from_secs(interval_secs)
+}
+
+/// Picks the database name from the CLI argument, then `env_name`, the value of the
+/// `TRYDB_DATABASE` env var, and only reads from `input` when neither is set. Empty
+/// values are ignored.
+fn resolve_db_name(
+    cli_arg: Option<String>,
+    env_name: Option<String>,
+    input: &mut impl BufRead,
+) -> io::Result<String> {
+    if let Some(name) = [cli_arg, env_name]
+        .into_iter()
+        .flatten()
+        .find(|name| !name.trim().is_empty())
+    {
+        return Ok(name.trim().to_owned());
+    }
+    read_db_name(input, &mut io::stdout())
+}
+
+/// Reads lines until one holds a name, asking again after each empty one. Running out
+/// of input first is an error, as there is no db to open.
+fn read_db_name(input: &mut impl BufRead, out: &mut impl Write) -> io::Result<String> {
+    loop {
+        let mut db_name = String::new();
+        if input.read_line(&mut db_name)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "no database name given",
+            ));
+        }
+        let db_name = db_name.trim();
+        if !db_name.is_empty() {
+            return Ok(db_name.to_owned());
+        }
+        write!(out, "The database name can't be empty, enter a name: ")?;
+        out.flush()?;
+    }
+}
+
+/// Exits with a failure code when the arguments are bad or the db can't be opened;
+/// leaving through `.exit` or end of input succeeds unless `.exit <code>` says otherwise.
+fn main() -> ExitCode {
+    let options = match CliOptions::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::FAILURE;
+        }
+    };
+    set_log_level(options.log_level());
+    set_fill_byte(options.fill_byte);
+    set_use_db_subdir(!options.no_subdir);
+    let db_name = match resolve_db_name(
+        options.db_name,
+        env::var(DB_NAME_ENV).ok(),
+        &mut io::stdin().lock(),
+    ) {
+        Ok(db_name) => db_name,
+        Err(err) => {
+            eprintln!("Failed to read the db name: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut config = TableConfig::new()
+        .append_only(options.append_only && !options.readonly)
+        .read_only(options.readonly)
+        .durable(options.durable);
+    if let Some(schema) = options.schema {
+        config = config.schema(schema);
+    }
+    if let Some(max_rows) = options.max_rows {
+        config = config.max_rows(max_rows);
+    }
+    let table = db_open(&db_name, &config);
+    match table {
+        Ok(table) => {
+            let interactive = io::stdin().is_terminal();
+            let quiet = options.quiet || options.json;
+            if let Some(banner) = startup_banner(&db_name, &table, quiet, interactive) {
+                println!("{}", banner);
+            }
+            let mut session = Session::new(table, io::stdout()).with_input(io::stdin().lock());
+            session.force = options.force;
+            if let Some(prompt) = options.prompt {
+                session.prompt = prompt;
+            }
+            if options.json {
+                session.json = true;
+                session.format.mode = OutputMode::Json;
+            }
+            let mut last_save = Instant::now();
+            loop {
+                let mut input_buffer = InputBuffer::new();
+                if !session.json {
+                    print_prompt(&session.prompt, &mut session.out);
+                }
+                read_input(&mut input_buffer, &mut session.input);
+                let res = run_input(&mut input_buffer, &mut session);
+                // Checked between commands, so an idle prompt saves with the next one.
+                if autosave_due(options.autosave_secs, last_save.elapsed()) {
+                    match db_autosave(&mut session.table) {
+                        Ok(failures) if !failures.is_empty() => {
+                            report_flush_failures(&failures, &mut io::stderr())
+                        }
+                        Ok(_) => {}
+                        Err(err) => eprintln!("Failed to autosave the db: {}", err),
+                    }
+                    last_save = Instant::now();
+                }
+                match res {
+                    Ok(_) => {}
+                    Err(Error::MetaCommandError) => {
+                        break;
+                    }
+                    Err(Error::MetaNoCommand) => {
+                        break;
+                    }
+                    Err(Error::MetaCommandExit) => {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            let start = Instant::now();
+            let closed = db_close(&mut session.table);
+            let elapsed = start.elapsed();
+            log!(LogLevel::Info, "Closing the db took {:?}", elapsed);
+            if !report_close_failures(closed, &mut io::stderr()) {
+                return ExitCode::FAILURE;
+            }
+            ExitCode::from(session.exit_code)
+        }
+        Err(err) => {
+            eprintln!("{:?}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The line shown before the first prompt, left out with `--quiet` or when the input
+/// isn't a terminal.
+fn startup_banner(db_name: &str, table: &Table, quiet: bool, interactive: bool) -> Option<String> {
+    if quiet || !interactive {
+        return None;
+    }
+    Some(format!(
+        "try-db v{} — database '{}' ({} rows)",
+        env!("CARGO_PKG_VERSION"),
+        db_name,
+        table.num_rows
+    ))
+}
+
+/// Runs one line of input, reporting how long it took when `.timer on` is set.
+fn run_input<W: Write>(
+    input_buffer: &mut InputBuffer,
+    session: &mut Session<W>,
+) -> Result<(), Error> {
+    let start = Instant::now();
+    let res = process_input(input_buffer, session);
+    if session.timer && !session.json {
+        outln!(session.out, "It took {:?}", start.elapsed());
+    }
+    res
+}
+
+fn process_input<W: Write>(
+    input_buffer: &mut InputBuffer,
+    session: &mut Session<W>,
+) -> Result<(), Error> {
+    if let Some(buffer_data) = input_buffer.buffer.take() {
+        // Meta commands take `--` options, so only statements carry comments.
+        let text = if buffer_data.starts_with('.') {
+            buffer_data.trim_end()
+        } else {
+            strip_comment(&buffer_data).trim_end()
+        };
+        if text.is_empty() {
+            return Ok(());
+        }
+        if session.echo {
+            outln!(session.out, "-- executing: {}", text);
+        }
+        if session.read_depth == 0 && meta_args(text, ".history").is_none() {
+            session.history.push(text.to_owned());
+        }
+        input_buffer.buffer = Some(text.to_owned());
+    }
+    match do_meta_command(input_buffer, session) {
+        MetaCommandResult::MetaCommandSuccess => Err(Error::MetaCommandExit),
+        MetaCommandResult::MetaCommandHandled => return Ok(()),
+        MetaCommandResult::MetaCommandUnrecognizedCommand => Ok(Error::MetaCommandError),
+        MetaCommandResult::MetaNoCommand => {
+            outln!(session.out, "No command is selected");
+            Err(Error::MetaNoCommand)
+        }
+    }?;
+    let explained = input_buffer
+        .buffer
+        .as_deref()
+        .and_then(|buffer_data| buffer_data.strip_prefix("explain "))
+        .map(|statement_text| statement_text.trim().to_owned());
+    let explain = explained.is_some();
+    if let Some(statement_text) = explained {
+        input_buffer.buffer = Some(statement_text);
+    }
+    let mut statement = Statement::new();
+    statement.schema = session.table.schema;
+    statement.fill = session.table.fill;
+    // Every prepare failure returns here, so only a statement with a type reaches execution.
+    let prepared = prepare_statement(input_buffer, &mut statement);
+    check_prepare_result(prepared, input_buffer, session.json, &mut session.out)?;
+    if explain {
+        execute_explain(&statement, &mut session.table, &mut session.out);
+        return Ok(());
+    }
+    match execute_statement(&statement, &mut session.table) {
+        Ok(result) => {
+            if let QueryResult::Inserted(count)
+            | QueryResult::Updated(count)
+            | QueryResult::Deleted(count) = result
+            {
+                session.changes = count;
+            }
+            if session.json {
+                print_json_result(&result, &mut session.out);
+            } else {
+                print_query_result(&result, session.format, &mut session.out);
+            }
+            Ok(())
+        }
+        Err(ExecuteResult::ExecuteTableFull) => {
+            let message = "Insert is not allowed, Table is full";
+            print_error(message, session.json, &mut session.out);
+            Err(TableFull)
+        }
+        Err(ExecuteResult::ExecuteFail) => {
+            print_error("Query execution failed", session.json, &mut session.out);
+            Err(ExecuteError)
+        }
+        Err(ExecuteResult::ExecuteReadOnly) => {
+            let message = format!("{:?}: the table can't be changed", Error::ReadOnly);
+            print_error(&message, session.json, &mut session.out);
+            Err(Error::ReadOnly)
+        }
+        Err(ExecuteResult::ExecuteAppendOnly) => {
+            let message = format!("{:?}: the table only accepts inserts", Error::AppendOnly);
+            print_error(&message, session.json, &mut session.out);
+            Err(Error::AppendOnly)
+        }
+        Err(ExecuteResult::ExecuteDuplicateKey) => {
+            print_error("Error: Duplicate key.", session.json, &mut session.out);
+            Err(Error::DuplicateKey)
+        }
+        Err(ExecuteResult::ExecuteNotFound) => {
+            // The write matched no row, so it changed none.
+            session.changes = 0;
+            print_error("Error: No row has that id.", session.json, &mut session.out);
+            Err(Error::NotFound)
+        }
+    }?;
+    Ok(())
+}
+
+/// How deep `.read` scripts may nest before further `.read`s are refused.
+const MAX_READ_DEPTH: usize = 4;
+
+/// Runs `.read <path> [--stop-on-error]`: each line of the file goes through
+/// `process_input` as if typed, so blank lines and `--` comments are skipped. A failing
+/// line is reported with its line number and, with `--stop-on-error`, ends the script.
+/// `.exit` in a script exits the REPL.
+fn execute_read<W: Write>(args: &str, session: &mut Session<W>) -> Result<(), Error> {
+    let (path, stop_on_error) = match args.split_whitespace().collect::<Vec<_>>()[..] {
+        [path] => (path, false),
+        [path, "--stop-on-error"] => (path, true),
+        _ => {
+            outln!(session.out, "Usage: .read <path> [--stop-on-error]");
+            return Ok(());
+        }
+    };
+    if session.read_depth >= MAX_READ_DEPTH {
+        outln!(session.out, "Error: .read is nested too deeply");
+        return Ok(());
+    }
+    let script = match fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(err) => {
+            outln!(session.out, "Error: cannot read {}: {}", path, err);
+            return Ok(());
+        }
+    };
+    session.read_depth += 1;
+    let res = run_script(&script, stop_on_error, session);
+    session.read_depth -= 1;
+    res
+}
+
+fn run_script<W: Write>(
+    script: &str,
+    stop_on_error: bool,
+    session: &mut Session<W>,
+) -> Result<(), Error> {
+    for (index, line) in script.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        let mut input_buffer = InputBuffer::new();
+        input_buffer.input_length = line.len() as i32;
+        input_buffer.buffer = Some(line.to_owned());
+        match process_input(&mut input_buffer, session) {
+            Ok(()) => {}
+            Err(Error::MetaCommandExit) => return Err(Error::MetaCommandExit),
+            Err(err) => {
+                outln!(session.out, "Error on line {}: {:?}", index + 1, err);
+                if stop_on_error {
+                    return Err(err);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Cuts a `--` comment off the end of a line, ignoring `--` inside quoted values.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '-') if matches!(chars.peek(), Some((_, '-'))) => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Reports a failed prepare to the user and maps it to the matching `Error`. Under
+/// `json` every failure prints an error object, even the ones that are otherwise silent.
+fn check_prepare_result(
+    result: PrepareResult,
+    input_buffer: &InputBuffer,
+    json: bool,
+    out: &mut impl Write,
+) -> Result<(), Error> {
+    match result {
+        PrepareResult::PrepareSuccess => Ok(()),
+        PrepareResult::PrepareUnrecognizedStatement => {
+            let message = format!(
+                "Unrecognized keyword at start of {:?}",
+                &input_buffer.buffer.clone()
+            );
+            print_error(&message, json, out);
+            Err(Error::PrepareUnrecognizedStatement)
+        }
+        PrepareResult::PrepareSyntaxError => {
+            print_error("Syntax error: could not parse statement", json, out);
+            Err(PrepareError)
+        }
+        PrepareResult::PrepareSyntaxErrorAt(position) => {
+            let message = format!("Syntax error near position {}", position);
+            print_error(&message, json, out);
+            Err(PrepareError)
+        }
+        PrepareResult::PrepareStringTooLong => {
+            if json {
+                print_error("Username or email is too long", json, out);
+            }
+            Err(PrepareStringTooLong)
+        }
+        PrepareResult::PrepareEmptyValue => {
+            print_error("Username and email can't be empty", json, out);
+            Err(Error::PrepareEmptyValue)
+        }
+        PrepareResult::PrepareNegativeId => {
+            if json {
+                print_error("Id can't be negative", json, out);
+            }
+            Err(Error::PrepareNegativeId)
+        }
+    }
+}
+
+/// Prints a failure message, as `{"error": ...}` under `json`.
+fn print_error(message: &str, json: bool, out: &mut impl Write) {
+    if json {
+        outln!(out, "{{\"error\": {}}}", json_string(message));
+    } else {
+        outln!(out, "{}", message);
+    }
+}
+
+fn print_prompt(prompt: &str, out: &mut impl Write) {
+    write!(out, "{}", prompt).expect("failed to write output");
+    out.flush().expect("failed to write output");
+}
+
+/// Reads one line into `buffer`. A line that is empty once its `\n` or `\r\n` ending is
+/// removed, or end of input, leaves the buffer as `None`.
+fn read_input(buffer: &mut InputBuffer, input: &mut impl BufRead) {
+    let mut line = String::new();
+    input.read_line(&mut line).unwrap();
+    let line = line.trim_end_matches(['\r', '\n']);
+    if line.is_empty() {
+        buffer.buffer = None;
+    } else {
+        buffer.input_length = line.len() as i32;
+        buffer.buffer = Some(line.trim_end().to_owned());
+    }
+}
+
+/// Returns the arguments of `line` when it invokes the meta command `name`. The name
+/// has to end the line or be followed by whitespace, so `.recover` doesn't match
+/// `.recoveryfoo.db`.
+fn meta_args<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let args = line.strip_prefix(name)?;
+    (args.is_empty() || args.starts_with(char::is_whitespace)).then_some(args)
+}
+
+fn do_meta_command<W: Write>(
+    input_buffer: &InputBuffer,
+    session: &mut Session<W>,
+) -> MetaCommandResult {
+    if let Some(buffer_data) = &input_buffer.buffer {
+        if matches!(buffer_data.as_str(), ".exit" | ".quit" | ".q") {
+            MetaCommandResult::MetaCommandSuccess
+        } else if let Some(code) = buffer_data.strip_prefix(".exit ") {
+            session.exit_code = code.trim().parse().unwrap_or_else(|_| {
+                outln!(
+                    session.out,
+                    "Warning: invalid exit code {:?}, exiting with 0",
+                    code.trim()
+                );
+                0
+            });
+            MetaCommandResult::MetaCommandSuccess
+        } else if let Some(pragma) = meta_args(buffer_data, ".pragma") {
+            execute_pragma(pragma.trim(), &mut session.table, &mut session.out);
+            MetaCommandResult::MetaCommandHandled
+        } else if buffer_data.eq(".stat") {
+            execute_stat(&session.table, &mut session.out);
+            MetaCommandResult::MetaCommandHandled
+        } else if let Some(statement_text) = buffer_data.strip_prefix(".validate ") {
+            execute_validate(statement_text.trim(), &session.table, &mut session.out);
+            MetaCommandResult::MetaCommandHandled
+        } else if let Some(new_name) = buffer_data.strip_prefix(".rename ") {
+            execute_rename(new_name.trim(), session);
+            MetaCommandResult::MetaCommandHandled
+        } else if let Some(name) = meta_args(buffer_data, ".recover") {
+            execute_recover(name.trim(), session);
+            MetaCommandResult::MetaCommandHandled
+        } else if buffer_data.eq(".databases") {
+            execute_databases(&session.table, &mut session.out);
+            MetaCommandResult::MetaCommandHandled
+        } else if buffer_data.eq(".constants") {
+            execute_constants(&mut session.out);
+            MetaCommandResult::MetaCommandHandled
+        } else if buffer_data.eq(".tables") {
+            execute_tables(&session.table, &mut session.out);
+            MetaCommandResult::MetaCommandHandled
+        } else if buffer_data.eq(".verify") {
+            execute_verify(&mut session.table, &mut session.out);
+            MetaCommandResult::MetaCommandHandled
+        } else if buffer_data.eq(".btree") {
+            execute_btree(&mut session.table, &mut session.out);
+            MetaCommandResult::MetaCommandHandled
+        } else if buffer_data.eq(".btree pages") {
+            execute_btree_pages(&session.table, &mut session.out);
+            MetaCommandResult::MetaCommandHandled
+        } else if buffer_data.eq(".shrink") {
+            match session.table.shrink() {
+                Ok(released) => outln!(session.out, "Released {} pages", released),
+                Err(err) => outln!(session.out, "Error: failed to shrink: {}", err),
+            }
+            MetaCommandResult::MetaCommandHandled
+        } else if let Some(file_name) = buffer_data.strip_prefix(".save ") {
+            execute_save(file_name.trim(), session);
+            MetaCommandResult::MetaCommandHandled
+        } else if let Some(file_name) = buffer_data.strip_prefix(".load ") {
+            execute_load(file_name.trim(), session);
+            MetaCommandResult::MetaCommandHandled
+        } else if let Some(count) = meta_args(buffer_data, ".seed") {
+            match count.trim().parse() {
+                Ok(count) => execute_seed(count, session),
+                Err(_) => outln!(session.out, "Usage: .seed <n>"),
+            }
+            MetaCommandResult::MetaCommandHandled
+        } else if buffer_data.eq(".clear") {
+            execute_clear(session);
+            MetaCommandResult::MetaCommandHandled
+        } else if let Some(mode) = meta_args(buffer_data, ".mode") {
+            match OutputMode::from_name(mode.trim()) {
+                Some(mode) => session.format.mode = mode,
+                None => outln!(session.out, "Usage: .mode default|list|column|json|insert"),
+            }
+            MetaCommandResult::MetaCommandHandled
+        } else if let Some(width) = meta_args(buffer_data, ".width") {
+            match width.trim().parse() {
+                Ok(width) if width > 0 => session.format.width = width,
+                _ => outln!(session.out, "Usage: .width <n>"),
+            }
+            MetaCommandResult::MetaCommandHandled
+        } else if let Some(count) = meta_args(buffer_data, ".history") {
+            let count = match count.trim() {
+                "" => Ok(DEFAULT_HISTORY_LENGTH),
+                count => count.parse(),
+            };
+            match count {
+                Ok(count) => {
+                    let start = session.history.len().saturating_sub(count);
+                    for command in &session.history[start..] {
+                        outln!(session.out, "{}", command);
+                    }
+                }
+                Err(_) => outln!(session.out, "Usage: .history [n]"),
+            }
+            MetaCommandResult::MetaCommandHandled
+        } else if let Some(args) = buffer_data.strip_prefix(".prompt ") {
+            match tokenize(args)[..] {
+                [ref main] => session.prompt = main.text.to_string(),
+                [ref main, ref continuation] => {
+                    session.prompt = main.text.to_string();
+                    session.continuation_prompt = continuation.text.to_string();
+                }
+                _ => outln!(session.out, "Usage: .prompt <main> [<continuation>]"),
+            }
+            MetaCommandResult::MetaCommandHandled
+        } else if buffer_data.eq(".changes") {
+            outln!(session.out, "{}", session.changes);
+            MetaCommandResult::MetaCommandHandled
+        } else if let Some(mode) = meta_args(buffer_data, ".timer") {
+            match mode.trim() {
+                "on" => session.timer = true,
+                "off" => session.timer = false,
+                _ => outln!(session.out, "Usage: .timer on|off"),
+            }
+            MetaCommandResult::MetaCommandHandled
+        } else if let Some(args) = meta_args(buffer_data, ".read") {
+            match execute_read(args, session) {
+                Err(Error::MetaCommandExit) => MetaCommandResult::MetaCommandSuccess,
+                _ => MetaCommandResult::MetaCommandHandled,
+            }
+        } else if let Some(mode) = meta_args(buffer_data, ".echo") {
+            match mode.trim() {
+                "on" => session.echo = true,
+                "off" => session.echo = false,
+                _ => outln!(session.out, "Usage: .echo on|off"),
+            }
+            MetaCommandResult::MetaCommandHandled
+        } else {
+            MetaCommandResult::MetaCommandUnrecognizedCommand
+        }
+    } else {
+        MetaCommandResult::MetaNoCommand
+    }
+}
+
+/// Runs `.recover [name]`, repairing the named file or, without a name, the open one.
+/// The open table is closed first and reopened from the repaired file.
+fn execute_recover<W: Write>(name: &str, session: &mut Session<W>) {
+    let current = session.table.file_name.clone();
+    let Some(target) = Some(name)
+        .filter(|name| !name.is_empty())
+        .or(current.as_deref())
+    else {
+        outln!(session.out, "An in-memory database has no file to recover");
+        return;
+    };
+    let target = target.to_owned();
+    let is_open = current.as_deref() == Some(target.as_str());
+    if is_open && session.table.read_only {
+        outln!(
+            session.out,
+            "{:?}: the table can't be changed",
+            Error::ReadOnly
+        );
+        return;
+    }
+    if is_open && !report_close_failures(db_close(&mut session.table), &mut session.out) {
+        return;
+    }
+    let schema = session.table.schema;
+    match recover_file(&target, schema) {
+        Ok(num_rows) => outln!(session.out, "Recovered {} rows in {:?}", num_rows, target),
+        Err(err) => {
+            outln!(session.out, "Could not recover {:?}: {:?}", target, err);
+            return;
+        }
+    }
+    if is_open {
+        match Table::open_with_schema(&target, schema) {
+            Ok(table) => session.table = table,
+            Err(err) => outln!(session.out, "Could not reopen {:?}: {:?}", target, err),
+        }
+    }
+}
+
+/// Flushes the table, moves its file to `new_name` in the db directory and reopens it
+/// there. An existing file is never overwritten.
+fn execute_rename<W: Write>(new_name: &str, session: &mut Session<W>) {
+    let Some(old_name) = session.table.file_name.clone() else {
+        outln!(session.out, "An in-memory database has no file to rename");
+        return;
+    };
+    if !is_valid_db_name(new_name) {
+        outln!(session.out, "Invalid database name: {:?}", new_name);
+        return;
+    }
+    let target = db_path(new_name);
+    if target.exists() {
+        outln!(session.out, "{:?} already exists", new_name);
+        return;
+    }
+    if !report_close_failures(db_close(&mut session.table), &mut session.out) {
+        return;
+    }
+    if let Err(err) = fs::rename(db_path(&old_name), &target) {
+        outln!(session.out, "Rename failed: {}", err);
+        return;
+    }
+    match Table::open_with_schema(new_name, session.table.schema) {
+        Ok(table) => session.table = table,
+        Err(err) => outln!(session.out, "Could not reopen {:?}: {:?}", new_name, err),
+    }
+}
+
+/// Writes `bytes` to a temporary file next to `path`, syncs it and renames it over
+/// `path`, so a crash leaves either the old file or the whole new one.
+fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    let written = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp_path, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written
+}
+
+/// Runs `.save <file>`: writes the table's image to a file in the db directory,
+/// replacing any file already there except the open one. The old file stays whole
+/// until the new one is complete.
+fn execute_save<W: Write>(file_name: &str, session: &mut Session<W>) {
+    if !is_valid_db_name(file_name) {
+        outln!(session.out, "Invalid database name: {:?}", file_name);
+        return;
+    }
+    if session.table.file_name.as_deref() == Some(file_name) {
+        outln!(session.out, "{:?} is the open database", file_name);
+        return;
+    }
+    let path = db_path(file_name);
+    let saved = session.table.to_bytes().and_then(|image| {
+        create_db_dir(path.parent().unwrap())?;
+        write_atomically(&path, &image).map_err(|_| Error::DbOpenError)
+    });
+    match saved {
+        Ok(()) => outln!(session.out, "Saved {} rows", session.table.num_rows),
+        Err(err) => outln!(session.out, "Save failed: {:?}", err),
+    }
+}
+
+/// Runs `.load <file>`: replaces an in-memory table with the one stored in a file in
+/// the db directory. The loaded table stays in memory; `.save` writes it back.
+fn execute_load<W: Write>(file_name: &str, session: &mut Session<W>) {
+    if session.table.file_name.is_some() {
+        outln!(
+            session.out,
+            "Only an in-memory database can be replaced by .load"
+        );
+        return;
+    }
+    if !is_valid_db_name(file_name) {
+        outln!(session.out, "Invalid database name: {:?}", file_name);
+        return;
+    }
+    let loaded = fs::read(db_path(file_name))
+        .map_err(|_| Error::DbOpenError)
+        .and_then(|bytes| Table::from_bytes(&bytes));
+    match loaded {
+        Ok(table) => {
+            session.table = table
+                .with_append_only(session.table.append_only)
+                .with_read_only(session.table.read_only);
+            outln!(session.out, "Loaded {} rows", session.table.num_rows);
+        }
+        Err(err) => outln!(session.out, "Load failed: {:?}", err),
+    }
+}
+
+/// Prints the open database's file name and where it lives on disk.
+fn execute_databases(table: &Table, out: &mut impl Write) {
+    match &table.file_name {
+        Some(file_name) => {
+            let path = db_path(file_name);
+            let full_path = fs::canonicalize(&path).unwrap_or(path);
+            outln!(out, "{}: {}", file_name, full_path.display());
+        }
+        None => outln!(out, "(in memory)"),
+    }
+}
+
+/// Prints the compiled-in sizes of the users row layout and its pages.
+fn execute_constants(out: &mut impl Write) {
+    outln!(out, "ROW_SIZE: {}", ROW_SIZE);
+    outln!(out, "ID_SIZE: {}", ID_SIZE);
+    outln!(out, "USERNAME_SIZE: {}", USERNAME_SIZE);
+    outln!(out, "EMAIL_SIZE: {}", EMAIL_SIZE);
+    outln!(out, "PAGE_SIZE: {}", PAGE_SIZE);
+    outln!(out, "ROWS_PER_PAGE: {}", ROWS_PER_PAGE);
+    outln!(out, "TABLE_MAX_ROWS: {}", TABLE_MAX_ROWS);
+}
+
+/// Lists the tables open in the session with their row counts. A session has one
+/// table, named after its file.
+fn execute_tables(table: &Table, out: &mut impl Write) {
+    let name = table.file_name.as_deref().unwrap_or("(in memory)");
+    outln!(out, "{}: {} rows", name, table.num_rows);
+}
+
+/// Checks `num_rows` against the rows actually stored, without changing either.
+fn execute_verify(table: &mut Table, out: &mut impl Write) {
+    match table.count_stored_rows() {
+        Ok(stored) if stored == table.num_rows => outln!(out, "ok: {} rows", stored),
+        Ok(stored) => outln!(
+            out,
+            "mismatch: num_rows is {} but {} rows are stored",
+            table.num_rows,
+            stored
+        ),
+        Err(err) => outln!(out, "verify failed: {:?}", err),
+    }
+}
+
+/// Prints the node tree from the root down, each node indented under its parent: an
+/// internal node lists its children with the key between each pair, and a leaf lists
+/// its keys.
+fn execute_btree(table: &mut Table, out: &mut impl Write) {
+    outln!(out, "Tree:");
+    if table.page_count() == 0 {
+        outln!(out, "(empty)");
+        return;
+    }
+    if let Err(err) = print_node(table, 0, 0, out) {
+        outln!(out, "btree failed: {:?}", err);
+    }
+}
+
+/// Lists the pages resident in the pager, in page order, with what their node header
+/// holds and whether they still have changes to flush. Nothing is read from disk.
+fn execute_btree_pages(table: &Table, out: &mut impl Write) {
+    let pager = &table.pager;
+    let mut loaded = 0;
+    for (page_num, page) in pager.pages.iter().enumerate() {
+        let Some(page) = page else {
+            continue;
+        };
+        loaded += 1;
+        let node = match node_type(&page[..]) {
+            Some(NodeType::NodeLeaf) => format!("leaf, {} rows", leaf_node_num_cells(&page[..])),
+            Some(NodeType::NodeInternal) => {
+                format!("internal, {} keys", internal_node_num_keys(&page[..]))
+            }
+            None => "not a node".to_owned(),
+        };
+        let dirty = if pager.dirty[page_num].is_some() {
+            " (dirty)"
+        } else {
+            ""
+        };
+        outln!(out, "page {}: {}{}", page_num, node, dirty);
+    }
+    if loaded == 0 {
+        outln!(out, "(no pages loaded)");
+    }
+}
+
+fn print_node(
+    table: &mut Table,
+    page_num: usize,
+    depth: usize,
+    out: &mut impl Write,
+) -> Result<(), ExecuteResult> {
+    // A path down never visits more pages than the table has.
+    if depth >= table.page_count() {
+        return Err(ExecuteResult::ExecuteFail);
+    }
+    let layout = table.layout;
+    let indent = "  ".repeat(depth);
+    let page = *get_page(&mut table.pager, page_num).map_err(|_| ExecuteResult::ExecuteFail)?;
+    match node_type(&page) {
+        Some(NodeType::NodeLeaf) => {
+            let num_cells = leaf_node_num_cells(&page);
+            outln!(
+                out,
+                "{}- leaf (page {}, size {})",
+                indent,
+                page_num,
+                num_cells
+            );
+            for cell_num in 0..num_cells.min(layout.rows_per_page) {
+                outln!(
+                    out,
+                    "{}  - {}",
+                    indent,
+                    leaf_node_key(&page, layout, cell_num)
+                );
+            }
+        }
+        Some(NodeType::NodeInternal) => {
+            let num_keys = internal_node_num_keys(&page).min(INTERNAL_NODE_MAX_CELLS);
+            outln!(
+                out,
+                "{}- internal (page {}, size {})",
+                indent,
+                page_num,
+                num_keys
+            );
+            for child_num in 0..=num_keys {
+                print_node(table, internal_node_child(&page, child_num), depth + 1, out)?;
+                if child_num < num_keys {
+                    outln!(
+                        out,
+                        "{}  - key {}",
+                        indent,
+                        internal_node_key(&page, child_num)
+                    );
+                }
+            }
+        }
+        None => return Err(ExecuteResult::ExecuteFail),
+    }
+    Ok(())
+}
+
+/// Appends `count` rows named `user<id>`, numbered on from the largest stored id, and
+/// says how many fit before the table filled up.
+fn execute_seed<W: Write>(count: usize, session: &mut Session<W>) {
+    let table = &mut session.table;
+    let mut next_id = 0;
+    for row_num in 0..table.num_rows {
+        match table.read_row(row_num) {
+            Ok(row) => next_id = next_id.max(row.id() as u64 + 1),
+            Err(err) => {
+                outln!(session.out, "Seed failed: {:?}", err);
+                return;
+            }
+        }
+    }
+    let rows = (next_id..).take(count).map_while(|id| {
+        let id = u32::try_from(id).ok()?;
+        Row::from_parts(id, format!("user{}", id), format!("user{}@example.com", id)).ok()
+    });
+    match table.bulk_insert(rows) {
+        Ok(seeded) if seeded < count => outln!(
+            session.out,
+            "Seeded {} of {} rows before the table filled up",
+            seeded,
+            count
+        ),
+        Ok(seeded) => outln!(session.out, "Seeded {} rows", seeded),
+        Err(err) => outln!(session.out, "Seed failed: {:?}", err),
+    }
+}
+
+/// Empties the table once the user confirms, or straight away with `--force`.
+fn execute_clear<W: Write>(session: &mut Session<W>) {
+    if session.table.read_only {
+        outln!(
+            session.out,
+            "{:?}: the table can't be changed",
+            Error::ReadOnly
+        );
+        return;
+    }
+    if session.table.append_only {
+        outln!(
+            session.out,
+            "{:?}: the table only accepts inserts",
+            Error::AppendOnly
+        );
+        return;
+    }
+    if !session.force {
+        write!(
+            session.out,
+            "Delete all {} rows? [y/N] ",
+            session.table.num_rows
+        )
+        .expect("failed to write output");
+        session.out.flush().expect("failed to write output");
+        let mut answer = String::new();
+        session.input.read_line(&mut answer).unwrap();
+        if !matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes") {
+            outln!(session.out, "Clear cancelled");
+            return;
+        }
+    }
+    match session.table.clear() {
+        Ok(cleared) => {
+            session.changes = cleared;
+            outln!(session.out, "Cleared {} rows", cleared);
+        }
+        Err(err) => outln!(session.out, "{:?}", err),
+    }
+}
+
+/// Runs only the prepare step for `statement_text` and reports whether it would execute.
+fn execute_validate(statement_text: &str, table: &Table, out: &mut impl Write) {
+    let mut input_buffer = InputBuffer::new();
+    input_buffer.buffer_length = statement_text.len() as i32;
+    input_buffer.buffer = Some(statement_text.to_owned());
+    let mut statement = Statement::new();
+    statement.schema = table.schema;
+    statement.fill = table.fill;
+    let prepared = prepare_statement(&input_buffer, &mut statement);
+    match check_prepare_result(prepared, &input_buffer, false, out) {
+        Ok(()) => outln!(out, "valid"),
+        Err(err) => outln!(out, "invalid: {:?}", err),
+    }
+}
+
+fn execute_stat(table: &Table, out: &mut impl Write) {
+    outln!(out, "rows: {}", table.num_rows);
+    outln!(out, "free rows: {}", table.free_rows());
+    outln!(out, "pages: {}", table.page_count());
+    outln!(out, "bytes used: {}", table.bytes_used());
+    outln!(out, "bytes allocated: {}", table.bytes_allocated());
+    let metrics = table.pager.metrics();
+    outln!(out, "pages read: {}", metrics.pages_read);
+    outln!(out, "pages written: {}", metrics.pages_written);
+}
+
+fn execute_pragma(pragma: &str, table: &mut Table, out: &mut impl Write) {
+    match pragma {
+        "page_count" => outln!(out, "{}", table.page_count()),
+        "page_size" => outln!(out, "{}", PAGE_SIZE),
+        "synchronous" => outln!(out, "{}", if table.pager.durable { "on" } else { "off" }),
+        "synchronous on" => table.pager.durable = true,
+        "synchronous off" => table.pager.durable = false,
+        _ => outln!(out, "Unknown pragma: {:?}", pragma),
+    }
+}
+
+fn prepare_statement(input_buffer: &InputBuffer, statement: &mut Statement) -> PrepareResult {
+    if let Some(buffer_data) = &input_buffer.buffer {
+        let keyword = buffer_data.split_whitespace().next().unwrap_or_default();
+        return match keyword {
+            "insert" if statement.schema == Schema::Products => {
+                statement.statement_type = Some(StatementType::StatementInsert);
+                match scan_fmt!(buffer_data, "insert {} {} {}", i32, String, f64) {
+                    Ok((id, name, price)) => {
+                        statement.product_to_insert = Product { id, name, price };
+                        validate_product(&statement.product_to_insert)
+                    }
+                    Err(_) => PrepareResult::PrepareSyntaxError,
+                }
+            }
+            "insert" if buffer_data.starts_with(INSERT_OR_REPLACE) => {
+                prepare_insert_or_replace(buffer_data, statement)
+            }
+            "insert" if buffer_data.starts_with(INSERT_RAW) => {
+                prepare_insert_raw(buffer_data, statement)
+            }
+            "insert" if buffer_data.contains('=') => prepare_named_insert(buffer_data, statement),
+            "insert" => prepare_positional_insert(buffer_data, statement),
+            "select" => prepare_select(buffer_data, statement),
+            "update" => prepare_update(buffer_data, statement),
+            "delete" => prepare_delete(buffer_data, statement),
+            _ => PrepareResult::PrepareUnrecognizedStatement,
+        };
+    }
+    PrepareResult::PrepareUnrecognizedStatement
+}
+
+/// A whitespace-separated word of a statement and the byte offset it starts at. A
+/// value in single or double quotes is one token, without its quotes; inside them a
+/// doubled quote stands for one.
+#[derive(Debug, PartialEq)]
+struct Token<'a> {
+    text: Cow<'a, str>,
+    start: usize,
+}
+
+fn tokenize(input: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        chars.next();
+        if c.is_whitespace() {
+            continue;
+        }
+        let text = if matches!(c, '\'' | '"') {
+            // An unterminated quote runs to the end of the input.
+            let mut text = Cow::Borrowed("");
+            let mut from = start + 1;
+            let mut end = input.len();
+            while let Some((i, next)) = chars.next() {
+                if next != c {
+                    continue;
+                }
+                if chars.next_if(|&(_, after)| after == c).is_none() {
+                    end = i;
+                    break;
+                }
+                text.to_mut().push_str(&input[from..=i]);
+                from = i + 2;
+            }
+            match text {
+                Cow::Borrowed(_) => Cow::Borrowed(&input[from..end]),
+                Cow::Owned(mut text) => {
+                    text.push_str(&input[from..end]);
+                    Cow::Owned(text)
+                }
+            }
+        } else {
+            let mut end = input.len();
+            while let Some(&(i, next)) = chars.peek() {
+                if next.is_whitespace() {
+                    end = i;
+                    break;
+                }
+                chars.next();
+            }
+            Cow::Borrowed(&input[start..end])
+        };
+        tokens.push(Token { text, start });
+    }
+    tokens
+}
+
+/// Parses `insert <id> <username> <email>`. A missing field is reported at the end of
+/// the statement and an id that isn't a number at the id itself.
+fn prepare_positional_insert(buffer_data: &str, statement: &mut Statement) -> PrepareResult {
+    statement.statement_type = Some(StatementType::StatementInsert);
+    let tokens = tokenize(buffer_data);
+    let [_, id, username, email, ..] = tokens.as_slice() else {
+        return PrepareResult::PrepareSyntaxErrorAt(buffer_data.len());
+    };
+    let id = match id.text.parse().map(user_id) {
+        Ok(Ok(parsed)) => parsed,
+        Ok(Err(PrepareResult::PrepareNegativeId)) => return PrepareResult::PrepareNegativeId,
+        _ => return PrepareResult::PrepareSyntaxErrorAt(id.start),
+    };
+    store_row(
+        Row::from_parts(id, username.text.as_ref(), email.text.as_ref()),
+        statement,
+    )
+}
+
+const INSERT_OR_REPLACE: &str = "insert or replace ";
+const INSERT_RAW: &str = "insert raw ";
+
+/// Parses `insert raw <id> <username> <email>` with both text values in hex, the way
+/// `select raw` prints them, so bytes that aren't UTF-8 can be stored.
+fn prepare_insert_raw(buffer_data: &str, statement: &mut Statement) -> PrepareResult {
+    statement.statement_type = Some(StatementType::StatementInsertRaw);
+    let tokens = tokenize(buffer_data);
+    let [_, _, id, username, email] = tokens.as_slice() else {
+        return PrepareResult::PrepareSyntaxErrorAt(buffer_data.len());
+    };
+    let id = match id.text.parse().map(user_id) {
+        Ok(Ok(parsed)) => parsed,
+        Ok(Err(PrepareResult::PrepareNegativeId)) => return PrepareResult::PrepareNegativeId,
+        _ => return PrepareResult::PrepareSyntaxErrorAt(id.start),
+    };
+    let (Some(username_bytes), Some(email_bytes)) = (unhex(&username.text), unhex(&email.text))
+    else {
+        return PrepareResult::PrepareSyntaxError;
+    };
+    match RawRow::from_parts(id, username_bytes, email_bytes, statement.fill) {
+        Ok(row) => {
+            statement.raw_row_to_insert = Some(row);
+            PrepareResult::PrepareSuccess
+        }
+        Err(err) => err,
+    }
+}
+
+/// Parses `insert or replace` followed by a positional or named users insert.
+fn prepare_insert_or_replace(buffer_data: &str, statement: &mut Statement) -> PrepareResult {
+    if statement.schema != Schema::Users {
+        return PrepareResult::PrepareSyntaxError;
+    }
+    let insert = format!("insert {}", &buffer_data[INSERT_OR_REPLACE.len()..]);
+    let result = if insert.contains('=') {
+        prepare_named_insert(&insert, statement)
+    } else {
+        prepare_positional_insert(&insert, statement)
+    };
+    statement.statement_type = Some(StatementType::StatementInsertOrReplace);
+    result
+}
+
+/// Parses `insert id=<n> username=<name> email=<email>` with the fields in any order.
+/// Every field is required exactly once.
+fn prepare_named_insert(buffer_data: &str, statement: &mut Statement) -> PrepareResult {
+    statement.statement_type = Some(StatementType::StatementInsert);
+    let (mut id, mut username, mut email) = (None, None, None);
+    for field in buffer_data.split_whitespace().skip(1) {
+        let Some((name, value)) = field.split_once('=') else {
+            return PrepareResult::PrepareSyntaxError;
+        };
+        let slot = match name {
+            "id" => &mut id,
+            "username" => &mut username,
+            "email" => &mut email,
+            _ => return PrepareResult::PrepareSyntaxError,
+        };
+        if slot.replace(value).is_some() {
+            return PrepareResult::PrepareSyntaxError;
+        }
+    }
+    let (Some(id), Some(username), Some(email)) = (id, username, email) else {
+        return PrepareResult::PrepareSyntaxError;
+    };
+    let Ok(id) = id.parse() else {
+        return PrepareResult::PrepareSyntaxError;
+    };
+    store_row(
+        user_id(id).and_then(|id| Row::from_parts(id, username, email)),
+        statement,
+    )
+}
+
+/// Parses `update <id> set <column>=<value> ...` for the users table. Pairs may be
+/// separated by whitespace or commas, and each column may be set once. The positional
+/// `update <id> <username> <email>` sets both columns, checked like an insert's.
+fn prepare_update(buffer_data: &str, statement: &mut Statement) -> PrepareResult {
+    statement.statement_type = Some(StatementType::StatementUpdate);
+    if statement.schema != Schema::Users {
+        return PrepareResult::PrepareSyntaxError;
+    }
+    let tokens = tokenize(buffer_data);
+    let [_, id, set, pairs @ ..] = tokens.as_slice() else {
+        return PrepareResult::PrepareSyntaxErrorAt(buffer_data.len());
+    };
+    statement.update_id = match id.text.parse().map(user_id) {
+        Ok(Ok(parsed)) => parsed,
+        Ok(Err(PrepareResult::PrepareNegativeId)) => return PrepareResult::PrepareNegativeId,
+        _ => return PrepareResult::PrepareSyntaxErrorAt(id.start),
+    };
+    statement.assignments.clear();
+    if set.text != "set" {
+        let (username, [email]) = (set, pairs) else {
+            return PrepareResult::PrepareSyntaxErrorAt(buffer_data.len());
+        };
+        return match Row::from_parts(
+            statement.update_id,
+            username.text.as_ref(),
+            email.text.as_ref(),
+        ) {
+            Ok(row) => {
+                statement.assignments = vec![
+                    (TextColumn::Username, row.username().to_owned()),
+                    (TextColumn::Email, row.email().to_owned()),
+                ];
+                PrepareResult::PrepareSuccess
+            }
+            Err(err) => err,
+        };
+    }
+    for pair in pairs {
+        let text = pair.text.trim_matches(',');
+        if text.is_empty() {
+            continue;
+        }
+        let Some((name, value)) = text.split_once('=') else {
+            return PrepareResult::PrepareSyntaxErrorAt(pair.start);
+        };
+        let Some(column) = TextColumn::from_name(name) else {
+            return PrepareResult::PrepareSyntaxErrorAt(pair.start);
+        };
+        if statement.assignments.iter().any(|(set, _)| *set == column) {
+            return PrepareResult::PrepareSyntaxErrorAt(pair.start);
+        }
+        let max_length = match column {
+            TextColumn::Username => USERNAME_MAX_LENGTH,
+            TextColumn::Email => EMAIL_MAX_LENGTH,
+        };
+        if value.is_empty() {
+            return PrepareResult::PrepareEmptyValue;
+        }
+        if value.len() > max_length {
+            return PrepareResult::PrepareStringTooLong;
+        }
+        statement.assignments.push((column, value.to_string()));
+    }
+    if statement.assignments.is_empty() {
+        return PrepareResult::PrepareSyntaxErrorAt(buffer_data.len());
+    }
+    PrepareResult::PrepareSuccess
+}
+
+/// Parses `delete <id>`, for either schema since both are keyed by id.
+fn prepare_delete(buffer_data: &str, statement: &mut Statement) -> PrepareResult {
+    statement.statement_type = Some(StatementType::StatementDelete);
+    match scan_fmt!(buffer_data, "delete {}", i64).map(user_id) {
+        Ok(Ok(id)) => {
+            statement.delete_id = id;
+            PrepareResult::PrepareSuccess
+        }
+        Ok(Err(err)) => err,
+        Err(_) => PrepareResult::PrepareSyntaxError,
+    }
+}
+
+/// Narrows a parsed user id to its stored `u32`. Ids are parsed wider than that so a
+/// negative one is reported as such rather than as a syntax error.
+fn user_id(id: i64) -> Result<u32, PrepareResult> {
+    if id < 0 {
+        return Err(PrepareResult::PrepareNegativeId);
+    }
+    u32::try_from(id).map_err(|_| PrepareResult::PrepareSyntaxError)
+}
+
+fn store_row(row: Result<Row, PrepareResult>, statement: &mut Statement) -> PrepareResult {
+    match row {
+        Ok(row) => {
+            statement.row_to_insert = row;
+            PrepareResult::PrepareSuccess
+        }
+        Err(err) => err,
+    }
+}
+
+/// Parses what follows `select into`: the new database's name, then optionally the
+/// same `where` filter a plain `select` takes.
+fn prepare_select_into(rest: &str, statement: &mut Statement) -> PrepareResult {
+    let (target, filter) = rest
+        .trim()
+        .split_once(char::is_whitespace)
+        .unwrap_or((rest.trim(), ""));
+    let filter = filter.trim();
+    if statement.schema != Schema::Users
+        || !is_valid_db_name(target)
+        || !(filter.is_empty() || filter.starts_with("where "))
+    {
+        return PrepareResult::PrepareSyntaxError;
+    }
+    let prepared = prepare_select(format!("select {}", filter).trim_end(), statement);
+    if !matches!(prepared, PrepareResult::PrepareSuccess) {
+        return prepared;
+    }
+    statement.into = Some(target.to_owned());
+    statement.statement_type = Some(StatementType::StatementSelectInto);
+    PrepareResult::PrepareSuccess
+}
+
+fn prepare_select(buffer_data: &str, statement: &mut Statement) -> PrepareResult {
+    if buffer_data.len() == 6 {
+        statement.statement_type = Some(StatementType::StatementSelect);
+        return PrepareResult::PrepareSuccess;
+    }
+    if buffer_data.split_whitespace().nth(1) == Some("where") {
+        if statement.schema != Schema::Users {
+            return PrepareResult::PrepareSyntaxError;
+        }
+        if let [_, _, column, operator, value] = tokenize(buffer_data).as_slice() {
+            if column.text == "email" && operator.text == "=" {
+                statement.predicate = Some(Predicate::EmailEquals(value.text.to_string()));
+                statement.statement_type = Some(StatementType::StatementSelect);
+                return PrepareResult::PrepareSuccess;
+            }
+            if column.text == "id" && operator.text == "=" {
+                return match value.text.parse().map(user_id) {
+                    Ok(Ok(id)) => {
+                        statement.predicate = Some(Predicate::IdEquals(id));
+                        statement.statement_type = Some(StatementType::StatementSelect);
+                        PrepareResult::PrepareSuccess
+                    }
+                    Ok(Err(err)) => err,
+                    Err(_) => PrepareResult::PrepareSyntaxErrorAt(value.start),
+                };
+            }
+        }
+        return match scan_fmt!(buffer_data, "select where username like {}", String) {
+            Ok(pattern) => {
+                statement.predicate = Some(Predicate::UsernameLike(pattern.to_lowercase()));
+                statement.statement_type = Some(StatementType::StatementSelect);
+                PrepareResult::PrepareSuccess
+            }
+            Err(_) => PrepareResult::PrepareSyntaxError,
+        };
+    }
+    if let Some(argument) = buffer_data
+        .strip_prefix("select len(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        let column = TextColumn::from_name(argument.trim());
+        if statement.schema != Schema::Users || column.is_none() {
+            return PrepareResult::PrepareSyntaxError;
+        }
+        statement.computed = column.map(Computed::Len);
+        statement.statement_type = Some(StatementType::StatementSelect);
+        return PrepareResult::PrepareSuccess;
+    }
+    if buffer_data.split_whitespace().nth(1) == Some("distinct") {
+        if statement.schema != Schema::Users || buffer_data != "select distinct username" {
+            return PrepareResult::PrepareSyntaxError;
+        }
+        statement.statement_type = Some(StatementType::StatementSelectDistinctUsername);
+        return PrepareResult::PrepareSuccess;
+    }
+    if let Some(aggregate) = buffer_data
+        .strip_prefix("select ")
+        .and_then(|rest| Aggregate::from_name(rest.trim()))
+    {
+        if statement.schema != Schema::Users {
+            return PrepareResult::PrepareSyntaxError;
+        }
+        statement.aggregate = Some(aggregate);
+        statement.statement_type = Some(StatementType::StatementSelect);
+        return PrepareResult::PrepareSuccess;
+    }
+    if let Some(rest) = buffer_data.strip_prefix("select into ") {
+        return prepare_select_into(rest, statement);
+    }
+    if buffer_data == "select raw" {
+        if statement.schema != Schema::Users {
+            return PrepareResult::PrepareSyntaxError;
+        }
+        statement.statement_type = Some(StatementType::StatementSelectRaw);
+        return PrepareResult::PrepareSuccess;
+    }
+    if buffer_data.starts_with("select count(*)") {
+        if statement.schema != Schema::Users || buffer_data != "select count(*) group by username" {
+            return PrepareResult::PrepareSyntaxError;
+        }
+        statement.statement_type = Some(StatementType::StatementSelectCountByUsername);
+        return PrepareResult::PrepareSuccess;
+    }
+    if buffer_data.split_whitespace().nth(1) == Some("tail") {
+        return match scan_fmt!(buffer_data, "select tail {}", usize) {
+            Ok(tail) => {
+                statement.tail = tail;
+                statement.statement_type = Some(StatementType::StatementSelectTail);
+                PrepareResult::PrepareSuccess
+            }
+            Err(_) => PrepareResult::PrepareSyntaxError,
+        };
+    }
+    if buffer_data.split_whitespace().nth(1) == Some("rowid") {
+        return match scan_fmt!(buffer_data, "select rowid {}", usize) {
+            Ok(row_num) => {
+                statement.row_num = row_num;
+                statement.statement_type = Some(StatementType::StatementSelectRowid);
+                PrepareResult::PrepareSuccess
+            }
+            Err(_) => PrepareResult::PrepareSyntaxError,
+        };
+    }
+    // Only users have an email to look up.
+    if statement.schema != Schema::Users {
+        return PrepareResult::PrepareSyntaxError;
+    }
+    match scan_fmt!(buffer_data, "select {} ", String) {
+        Ok(email) => {
+            statement.email = email;
+            statement.statement_type = Some(StatementType::StatementSelectWithEmail);
+            PrepareResult::PrepareSuccess
+        }
+        Err(_) => PrepareResult::PrepareSyntaxError,
+    }
+}
+
+fn validate_row(row: &Row) -> PrepareResult {
+    if row.username().is_empty() || row.email().is_empty() {
+        return PrepareResult::PrepareEmptyValue;
+    }
+    if row.email().len() > EMAIL_MAX_LENGTH || row.username().len() > USERNAME_MAX_LENGTH {
+        return PrepareResult::PrepareStringTooLong;
+    }
+    // Control characters could be the fill byte, which would cut the value short.
+    if [row.username(), row.email()]
+        .iter()
+        .any(|value| value.bytes().any(|b| b.is_ascii_control()))
+    {
+        return PrepareResult::PrepareSyntaxError;
+    }
+    PrepareResult::PrepareSuccess
+}
+
+fn validate_product(product: &Product) -> PrepareResult {
+    if product.id < 0 {
+        return PrepareResult::PrepareNegativeId;
+    }
+    if product.name.len() > PRODUCT_NAME_MAX_LENGTH {
+        return PrepareResult::PrepareStringTooLong;
+    }
+    PrepareResult::PrepareSuccess
+}
+
+/// Describes how a prepared statement maps onto rows and pages without running it.
+fn execute_explain(statement: &Statement, table: &mut Table, out: &mut impl Write) {
+    let Some(statement_type) = &statement.statement_type else {
+        return;
+    };
+    outln!(out, "{:?}", statement_type);
+    match statement_type {
+        StatementType::StatementInsert => {
+            let key = match table.schema {
+                Schema::Users => statement.row_to_insert.id(),
+                Schema::Products => statement.product_to_insert.id as u32,
+            };
+            let row_num = Cursor::find(table, key).map_or(0, |cursor| cursor.row_num);
+            if table.schema == Schema::Products {
+                let product = &statement.product_to_insert;
+                outln!(
+                    out,
+                    "  id: {}, name: {:?}, price: {}",
+                    product.id,
+                    product.name,
+                    product.price
+                );
+            } else {
+                let row = &statement.row_to_insert;
+                outln!(
+                    out,
+                    "  id: {}, username: {:?}, email: {:?}",
+                    row.id(),
+                    row.username(),
+                    row.email()
+                );
+            }
+            // Where the row lands before any split; a full leaf splits, which takes a page.
+            let (page_num, cell_num, allocates) = match table.insert_target(key) {
+                Ok((page_num, cell_num, is_full)) => (page_num, cell_num, is_full),
+                Err(_) => (0, 0, true),
+            };
+            outln!(
+                out,
+                "  target row: {} (page {}, cell {}, byte offset {})",
+                row_num,
+                page_num,
+                cell_num,
+                table.layout.value_offset(cell_num)
+            );
+            outln!(out, "  allocates a new page: {}", allocates);
+        }
+        StatementType::StatementInsertOrReplace => {
+            let row = &statement.row_to_insert;
+            outln!(
+                out,
+                "  id: {}, username: {:?}, email: {:?}",
+                row.id(),
+                row.username(),
+                row.email()
+            );
+            outln!(
+                out,
+                "  finds id {} from the root, else inserts it",
+                row.id()
+            );
+        }
+        StatementType::StatementInsertRaw => {
+            if let Some(row) = &statement.raw_row_to_insert {
+                let fields = row.fields();
+                outln!(
+                    out,
+                    "  id: {}, username: {}, email: {}",
+                    fields[0].value,
+                    fields[1].value,
+                    fields[2].value
+                );
+            }
+        }
+        StatementType::StatementSelect => {
+            outln!(
+                out,
+                "  scans rows 0..{} across {} pages",
+                table.num_rows,
+                table.page_count()
+            );
+            if let Some(predicate) = &statement.predicate {
+                outln!(out, "  filter: {:?}", predicate);
+            }
+            if let Some(computed) = statement.computed {
+                outln!(out, "  computes: {}", computed.name());
+            }
+            if let Some(aggregate) = statement.aggregate {
+                outln!(out, "  aggregates: {}", aggregate.name());
+            }
+        }
+        StatementType::StatementSelectWithEmail => {
+            outln!(
+                out,
+                "  scans rows 0..{} for email {:?}",
+                table.num_rows,
+                statement.email
+            );
+        }
+        StatementType::StatementSelectRowid => match table.row_location(statement.row_num) {
+            Ok((page_num, _)) => {
+                outln!(out, "  reads row {} (page {})", statement.row_num, page_num)
+            }
+            Err(_) => outln!(out, "  reads row {}, past the last row", statement.row_num),
+        },
+        StatementType::StatementSelectTail => {
+            let start = table.num_rows - statement.tail.min(table.num_rows);
+            outln!(out, "  scans rows {}..{}", start, table.num_rows);
+        }
+        StatementType::StatementSelectDistinctUsername => {
+            outln!(
+                out,
+                "  scans rows 0..{}, skipping usernames already printed",
+                table.num_rows
+            );
+        }
+        StatementType::StatementSelectCountByUsername => {
+            outln!(
+                out,
+                "  scans rows 0..{}, counting rows per username",
+                table.num_rows
+            );
+        }
+        StatementType::StatementSelectRaw => {
+            outln!(out, "  scans rows 0..{} as stored bytes", table.num_rows);
+        }
+        StatementType::StatementSelectInto => {
+            outln!(out, "  scans rows 0..{}", table.num_rows);
+            if let Some(predicate) = &statement.predicate {
+                outln!(out, "  filter: {:?}", predicate);
+            }
+            outln!(
+                out,
+                "  copies rows into {:?}",
+                statement.into.as_deref().unwrap_or("")
+            );
+        }
+        StatementType::StatementUpdate => {
+            outln!(out, "  finds id {} from the root", statement.update_id);
+            for (column, value) in &statement.assignments {
+                outln!(out, "  sets {:?} to {:?}", column, value);
+            }
+        }
+        StatementType::StatementDelete => {
+            outln!(
+                out,
+                "  finds id {} from the root and removes its cell",
+                statement.delete_id
+            );
+        }
+    }
+}
+
+fn execute_statement(
+    statement: &Statement,
+    table: &mut Table,
+) -> Result<QueryResult, ExecuteResult> {
+    match &statement.statement_type {
+        None => Err(ExecuteResult::ExecuteFail),
+        Some(
+            StatementType::StatementInsert
+            | StatementType::StatementInsertOrReplace
+            | StatementType::StatementInsertRaw
+            | StatementType::StatementUpdate
+            | StatementType::StatementDelete,
+        ) if table.read_only => Err(ExecuteResult::ExecuteReadOnly),
+        Some(stmt) => match stmt {
+            StatementType::StatementInsert => execute_insert(statement, table),
+            StatementType::StatementInsertOrReplace => execute_insert_or_replace(statement, table),
+            StatementType::StatementInsertRaw => execute_insert_raw(statement, table),
+            StatementType::StatementSelect => execute_select(statement, table),
+            StatementType::StatementSelectWithEmail => {
+                execute_select_with_email(&statement.email, table)
+            }
+            StatementType::StatementSelectRowid => execute_select_rowid(statement.row_num, table),
+            StatementType::StatementSelectTail => execute_select_tail(statement.tail, table),
+            StatementType::StatementSelectDistinctUsername => {
+                execute_select_distinct_username(table)
+            }
+            StatementType::StatementSelectCountByUsername => {
+                execute_select_count_by_username(table)
+            }
+            StatementType::StatementSelectRaw => execute_select_raw(table),
+            StatementType::StatementSelectInto => execute_select_into(statement, table),
+            StatementType::StatementUpdate => execute_update(statement, table),
+            StatementType::StatementDelete => execute_delete(statement.delete_id, table),
+        },
+    }
+}
+
+fn execute_insert(statement: &Statement, table: &mut Table) -> Result<QueryResult, ExecuteResult> {
+    if table.num_rows >= table.max_rows {
+        return Err(ExecuteTableFull);
+    }
+    match table.schema {
+        Schema::Users => table.insert_row(&statement.row_to_insert),
+        Schema::Products => table.insert_product(&statement.product_to_insert),
+    }?;
+    Ok(QueryResult::Inserted(1))
+}
+fn execute_insert_raw(
+    statement: &Statement,
+    table: &mut Table,
+) -> Result<QueryResult, ExecuteResult> {
+    if table.num_rows >= table.max_rows {
+        return Err(ExecuteTableFull);
+    }
+    let row = statement
+        .raw_row_to_insert
+        .as_ref()
+        .ok_or(ExecuteResult::ExecuteFail)?;
+    table.insert_raw_row(row)?;
+    Ok(QueryResult::Inserted(1))
+}
+/// Overwrites the row sharing the new row's id in place, or inserts it when there is
+/// none. Append-only tables can't be replaced into.
+fn execute_insert_or_replace(
+    statement: &Statement,
+    table: &mut Table,
+) -> Result<QueryResult, ExecuteResult> {
+    let row = &statement.row_to_insert;
+    let mut cursor = Cursor::find(table, row.id())?;
+    if !cursor.end_of_table && row_key(cursor.cursor_value()?) == row.id() {
+        if cursor.table.append_only {
+            return Err(ExecuteResult::ExecuteAppendOnly);
+        }
+        cursor.table.write_row(cursor.row_num, row)?;
+        return Ok(QueryResult::Inserted(1));
+    }
+    execute_insert(statement, table)
+}
+
+/// Rewrites the row with the statement's id, keeping the stored value of every column
+/// the update doesn't set. Like a replace, it can't touch an append-only table, and
+/// an id no row has is reported rather than updating nothing.
+fn execute_update(statement: &Statement, table: &mut Table) -> Result<QueryResult, ExecuteResult> {
+    let mut cursor = Cursor::find(table, statement.update_id)?;
+    if cursor.end_of_table {
+        return Err(ExecuteResult::ExecuteNotFound);
+    }
+    let existing = cursor.cursor_row()?;
+    if existing.id() != statement.update_id {
+        return Err(ExecuteResult::ExecuteNotFound);
+    }
+    if cursor.table.append_only {
+        return Err(ExecuteResult::ExecuteAppendOnly);
+    }
+    let (mut username, mut email) = (existing.username(), existing.email());
+    for (column, value) in &statement.assignments {
+        match column {
+            TextColumn::Username => username = value,
+            TextColumn::Email => email = value,
+        }
+    }
+    let row =
+        Row::from_parts(existing.id(), username, email).map_err(|_| ExecuteResult::ExecuteFail)?;
+    cursor.table.write_row(cursor.row_num, &row)?;
+    Ok(QueryResult::Updated(1))
+}
+
+/// Removes the row with `id`, closing the gap it leaves in its leaf. Like an update,
+/// it can't touch an append-only table.
+fn execute_delete(id: u32, table: &mut Table) -> Result<QueryResult, ExecuteResult> {
+    let mut cursor = Cursor::find(table, id)?;
+    if cursor.end_of_table || row_key(cursor.cursor_value()?) != id {
+        return Err(ExecuteResult::ExecuteNotFound);
+    }
+    if cursor.table.append_only {
+        return Err(ExecuteResult::ExecuteAppendOnly);
+    }
+    cursor.table.delete_row(cursor.row_num)?;
+    Ok(QueryResult::Deleted(1))
+}
+
+fn execute_select_with_email(
+    email: &String,
+    table: &mut Table,
+) -> Result<QueryResult, ExecuteResult> {
+    let mut found = None;
+    let start = Instant::now();
+    let mut cursor = Cursor::table_start(table);
+    while !cursor.end_of_table {
+        let row = cursor.cursor_row()?;
+        if row.email().eq(email) {
+            found = Some((cursor.row_num, row));
+            break;
+        }
+        cursor.cursor_advance();
+    }
+    let elapsed = start.elapsed();
+    log!(
+        LogLevel::Debug,
+        "It took {:?} to complete the select with email",
+        elapsed
+    );
+    Ok(QueryResult::Found(found))
+}
+/// Selects the row at `row_num` in id order, which is how rows are stored; positions
+/// past the end select nothing.
+fn execute_select_rowid(row_num: usize, table: &mut Table) -> Result<QueryResult, ExecuteResult> {
+    select_rows(table, row_num..row_num + 1, None)
+}
+
+/// Selects the `tail` rows with the highest ids, or all of them when there are fewer.
+/// Rows are stored in id order, so these are the last ones stored, not the last
+/// ones inserted.
+fn execute_select_tail(tail: usize, table: &mut Table) -> Result<QueryResult, ExecuteResult> {
+    let start = table.num_rows - tail.min(table.num_rows);
+    select_rows(table, start..table.num_rows, None)
+}
+
+fn execute_select(statement: &Statement, table: &mut Table) -> Result<QueryResult, ExecuteResult> {
+    if let Some(computed) = statement.computed {
+        return execute_select_computed(computed, table);
+    }
+    if let Some(aggregate) = statement.aggregate {
+        return execute_select_aggregate(aggregate, table);
+    }
+    let num_rows = table.num_rows;
+    select_rows(table, 0..num_rows, statement.predicate.as_ref())
+}
+
+/// Evaluates `computed` for every row in place of the row itself.
+fn execute_select_computed(
+    computed: Computed,
+    table: &mut Table,
+) -> Result<QueryResult, ExecuteResult> {
+    let mut values = Vec::new();
+    let mut cursor = Cursor::table_start(table);
+    while !cursor.end_of_table {
+        let row = cursor.cursor_row()?;
+        values.push((cursor.row_num, computed.evaluate(&row)));
+        cursor.cursor_advance();
+    }
+    Ok(QueryResult::Computed(computed, values))
+}
+
+/// Folds every row's id in one walk of the table.
+fn execute_select_aggregate(
+    aggregate: Aggregate,
+    table: &mut Table,
+) -> Result<QueryResult, ExecuteResult> {
+    let (mut min, mut max, mut sum, mut count) = (u32::MAX, 0, 0u64, 0usize);
+    let mut cursor = Cursor::table_start(table);
+    while !cursor.end_of_table {
+        let id = cursor.cursor_row()?.id();
+        min = min.min(id);
+        max = max.max(id);
+        sum += u64::from(id);
+        count += 1;
+        cursor.cursor_advance();
+    }
+    let value = match aggregate {
+        _ if count == 0 => None,
+        Aggregate::Min => Some(f64::from(min)),
+        Aggregate::Max => Some(f64::from(max)),
+        Aggregate::Avg => Some(sum as f64 / count as f64),
+    };
+    Ok(QueryResult::Aggregated(aggregate, value))
+}
+
+/// Selects each username once, in the order they first appear.
+fn execute_select_distinct_username(table: &mut Table) -> Result<QueryResult, ExecuteResult> {
+    let mut seen = HashSet::new();
+    let mut usernames = Vec::new();
+    let mut cursor = Cursor::table_start(table);
+    while !cursor.end_of_table {
+        let row = cursor.cursor_row()?;
+        cursor.cursor_advance();
+        if seen.insert(row.username().to_owned()) {
+            usernames.push(row.username().to_owned());
+        }
+    }
+    Ok(QueryResult::Usernames(usernames))
+}
+
+/// Copies the rows the statement's filter keeps into a new users database. An existing
+/// file is never overwritten.
+fn execute_select_into(
+    statement: &Statement,
+    table: &mut Table,
+) -> Result<QueryResult, ExecuteResult> {
+    let Some(target) = statement.into.clone() else {
+        return Err(ExecuteResult::ExecuteFail);
+    };
+    if db_path(&target).exists() {
+        return Err(ExecuteResult::ExecuteFail);
+    }
+    let num_rows = table.num_rows;
+    let QueryResult::Selected(rows) =
+        select_rows(table, 0..num_rows, statement.predicate.as_ref())?
+    else {
+        return Err(ExecuteResult::ExecuteFail);
+    };
+    let mut copy =
+        Table::open_with_schema(&target, Schema::Users).map_err(|_| ExecuteResult::ExecuteFail)?;
+    let copied = copy.bulk_insert(rows.into_iter().map(|(_, row)| row));
+    let closed = db_close(&mut copy);
+    match copied {
+        Ok(rows) if closed.is_ok_and(|failures| failures.is_empty()) => Ok(QueryResult::Copied {
+            file_name: target,
+            rows,
+        }),
+        _ => Err(ExecuteResult::ExecuteFail),
+    }
+}
+
+/// Selects every users row with its text fields as the stored bytes.
+fn execute_select_raw(table: &mut Table) -> Result<QueryResult, ExecuteResult> {
+    let mut rows = Vec::new();
+    let mut cursor = Cursor::table_start(table);
+    while !cursor.end_of_table {
+        rows.push((cursor.row_num, cursor.table.read_raw_row(cursor.row_num)?));
+        cursor.cursor_advance();
+    }
+    Ok(QueryResult::SelectedRaw(rows))
+}
+
+/// Counts the rows of each username, sorted by username so the output doesn't
+/// depend on hashing.
+fn execute_select_count_by_username(table: &mut Table) -> Result<QueryResult, ExecuteResult> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut cursor = Cursor::table_start(table);
+    while !cursor.end_of_table {
+        let row = cursor.cursor_row()?;
+        *counts.entry(row.username().to_owned()).or_default() += 1;
+        cursor.cursor_advance();
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort();
+    Ok(QueryResult::Counts(counts))
+}
+
+/// Reads the stored rows in `rows` using the table's schema, keeping each with its
+/// row number. `predicate` filters users rows; products have no predicates yet.
+fn select_rows(
+    table: &mut Table,
+    rows: Range<usize>,
+    predicate: Option<&Predicate>,
+) -> Result<QueryResult, ExecuteResult> {
+    let schema = table.schema;
+    let mut cursor = Cursor::at_row(table, rows.start);
+    let mut users = Vec::new();
+    let mut products = Vec::new();
+    while !cursor.end_of_table && cursor.row_num < rows.end {
+        match schema {
+            Schema::Users => {
+                let row = cursor.cursor_row()?;
+                if predicate.is_none_or(|predicate| predicate.matches(&row)) {
+                    users.push((cursor.row_num, row));
+                }
+            }
+            Schema::Products => {
+                let mut product = Product::new();
+                deserialize_product(cursor.cursor_value()?, &mut product);
+                products.push((cursor.row_num, product));
+            }
+        }
+        cursor.cursor_advance();
+    }
+    Ok(match schema {
+        Schema::Users => QueryResult::Selected(users),
+        Schema::Products => QueryResult::SelectedProducts(products),
+    })
+}
+
+/// Prints what a statement produced as JSON lines: rows as objects, and one summary
+/// object for everything else.
+fn print_json_result(result: &QueryResult, out: &mut impl Write) {
+    let format = OutputFormat {
+        mode: OutputMode::Json,
+        ..OutputFormat::default()
+    };
+    match result {
+        QueryResult::Inserted(rows) => outln!(out, "{{\"inserted\": {}}}", rows),
+        QueryResult::Updated(rows) => outln!(out, "{{\"updated\": {}}}", rows),
+        QueryResult::Deleted(rows) => outln!(out, "{{\"deleted\": {}}}", rows),
+        QueryResult::Copied { file_name, rows } => outln!(
+            out,
+            "{{\"copied\": {}, \"file\": {}}}",
+            rows,
+            json_string(file_name)
+        ),
+        QueryResult::Aggregated(aggregate, None) => {
+            outln!(out, "{{{}: null}}", json_string(aggregate.name()))
+        }
+        QueryResult::Found(Some((index, row))) => {
+            outln!(out, "{}", format_record(*index, row, &row.fields(), format))
+        }
+        _ => print_query_result(result, format, out),
+    }
+}
+
+/// Prints what a statement produced in the session's output mode. Inserts and updates
+/// print nothing, and a select that found no rows says so unless the output is meant
+/// to be read back by a program.
+fn print_query_result(result: &QueryResult, format: OutputFormat, out: &mut impl Write) {
+    let no_rows = match result {
+        QueryResult::Selected(rows) => rows.is_empty(),
+        QueryResult::SelectedRaw(rows) => rows.is_empty(),
+        QueryResult::SelectedProducts(products) => products.is_empty(),
+        _ => false,
+    };
+    if no_rows && !matches!(format.mode, OutputMode::Json | OutputMode::Insert) {
+        outln!(out, "(0 rows)");
+        return;
+    }
+    match result {
+        QueryResult::Inserted(_) | QueryResult::Updated(_) | QueryResult::Deleted(_) => {}
+        QueryResult::Selected(rows) => {
+            for (index, row) in rows {
+                outln!(out, "{}", format_record(*index, row, &row.fields(), format));
+            }
+        }
+        QueryResult::Aggregated(aggregate, None) => {
+            outln!(out, "{}: no rows", aggregate.name())
+        }
+        QueryResult::Aggregated(aggregate, Some(value)) => {
+            let value = match aggregate {
+                Aggregate::Avg => format!("{:.2}", value),
+                Aggregate::Min | Aggregate::Max => value.to_string(),
+            };
+            let fields = [Field {
+                name: aggregate.name(),
+                value: value.clone(),
+                is_text: false,
+            }];
+            outln!(out, "{}", format_record(0, &value, &fields, format));
+        }
+        QueryResult::Copied { file_name, rows } => {
+            outln!(out, "Copied {} rows into {:?}", rows, file_name)
+        }
+        QueryResult::SelectedRaw(rows) => {
+            for (index, row) in rows {
+                outln!(out, "{}", format_record(*index, row, &row.fields(), format));
+            }
+        }
+        QueryResult::SelectedProducts(products) => {
+            for (index, product) in products {
+                let line = format_record(*index, product, &product.fields(), format);
+                outln!(out, "{}", line);
+            }
+        }
+        QueryResult::Computed(computed, values) => {
+            for (index, value) in values {
+                let fields = [Field {
+                    name: computed.name(),
+                    value: value.to_string(),
+                    is_text: false,
+                }];
+                outln!(out, "{}", format_record(*index, value, &fields, format));
+            }
+        }
+        QueryResult::Usernames(usernames) => {
+            for (index, username) in usernames.iter().enumerate() {
+                let fields = [Field {
+                    name: "username",
+                    value: username.clone(),
+                    is_text: true,
+                }];
+                outln!(out, "{}", format_record(index, username, &fields, format));
+            }
+        }
+        QueryResult::Counts(counts) => {
+            for (index, (username, count)) in counts.iter().enumerate() {
+                let fields = [
+                    Field {
+                        name: "username",
+                        value: username.clone(),
+                        is_text: true,
+                    },
+                    Field {
+                        name: "count",
+                        value: count.to_string(),
+                        is_text: false,
+                    },
+                ];
+                let line = format_record(index, &(username, count), &fields, format);
+                outln!(out, "{}", line);
+            }
+        }
+        QueryResult::Found(Some((index, row))) => {
+            outln!(out, "Found the row {:?} \n at index {}", row, index)
+        }
+        QueryResult::Found(None) => {}
+    }
+}
+
+/// Renders one row for display. Only column mode shortens values; stored data is
+/// never affected.
+fn format_record(
+    index: usize,
+    record: &impl fmt::Debug,
+    fields: &[Field],
+    format: OutputFormat,
+) -> String {
+    match format.mode {
+        OutputMode::Default => format!("Row {} {:?}", index, record),
+        OutputMode::List => fields
+            .iter()
+            .map(|field| field.value.as_str())
+            .collect::<Vec<_>>()
+            .join("|"),
+        OutputMode::Column => fields
+            .iter()
+            .map(|field| {
+                format!(
+                    "{:<1$}",
+                    truncate_for_display(&field.value, format.width),
+                    format.width
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_owned(),
+        OutputMode::Json => {
+            let members: Vec<String> = fields
+                .iter()
+                .map(|field| {
+                    let value = if field.is_text {
+                        json_string(&field.value)
+                    } else {
+                        field.value.clone()
+                    };
+                    format!("{}: {}", json_string(field.name), value)
+                })
+                .collect();
+            format!("{{{}}}", members.join(", "))
+        }
+        OutputMode::Insert => {
+            let values: Vec<Cow<str>> = fields
+                .iter()
+                .map(|field| quote_token(&field.value))
+                .collect();
+            format!("insert {}", values.join(" "))
+        }
+    }
+}
+
+/// Writes `value` the way `tokenize` reads it back as one token. Values that could be
+/// split, taken for a quote or cut off as a comment are put in single quotes, with any
+/// single quote inside doubled.
+fn quote_token(value: &str) -> Cow<'_, str> {
+    let plain = !value.is_empty()
+        && !value.contains(|c: char| c.is_whitespace() || matches!(c, '\'' | '"'))
+        && !value.contains("--");
+    if plain {
+        return Cow::Borrowed(value);
+    }
+    Cow::Owned(format!("'{}'", value.replace('\'', "''")))
+}
+
+/// Cuts `value` to `width` characters, ending in an ellipsis when anything was dropped.
+fn truncate_for_display(value: &str, width: usize) -> String {
+    if value.chars().count() <= width {
+        return value.to_owned();
+    }
+    let mut truncated: String = value.chars().take(width.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn serialize_product(source: &Product, destination: &mut [u8]) {
+    destination[PRODUCT_ID_OFFSET..PRODUCT_ID_OFFSET + PRODUCT_ID_SIZE]
+        .copy_from_slice(&source.id.to_ne_bytes());
+    let name = &mut destination[PRODUCT_NAME_OFFSET..PRODUCT_NAME_OFFSET + PRODUCT_NAME_SIZE];
+    let name_bytes = truncate_to_char_boundary(&source.name, PRODUCT_NAME_MAX_LENGTH).as_bytes();
+    let name_length = name_bytes.len();
+    name[..name_length].copy_from_slice(&name_bytes[..name_length]);
+    name[name_length..].fill(0);
+    destination[PRODUCT_PRICE_OFFSET..PRODUCT_PRICE_OFFSET + PRODUCT_PRICE_SIZE]
+        .copy_from_slice(&source.price.to_ne_bytes());
+}
+
+fn deserialize_product(source: &[u8], destination: &mut Product) {
+    let id_bytes = &source[PRODUCT_ID_OFFSET..PRODUCT_ID_OFFSET + PRODUCT_ID_SIZE];
+    destination.id = i32::from_ne_bytes(id_bytes.try_into().unwrap());
+    let name_bytes = &source[PRODUCT_NAME_OFFSET..PRODUCT_NAME_OFFSET + PRODUCT_NAME_SIZE];
+    destination.name = read_null_terminated(name_bytes);
+    let price_bytes = &source[PRODUCT_PRICE_OFFSET..PRODUCT_PRICE_OFFSET + PRODUCT_PRICE_SIZE];
+    destination.price = f64::from_ne_bytes(price_bytes.try_into().unwrap());
+}
+
+/// The longest prefix of `value` that fits in `max_bytes` without splitting a character.
+fn truncate_to_char_boundary(value: &str, max_bytes: usize) -> &str {
+    if value.len() <= max_bytes {
+        return value;
+    }
+    let mut end = max_bytes;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+fn read_null_terminated(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, Read, Seek, Write};
+    use std::path::Path;
+    use std::time::Duration;
+
+    use crate::{
+        autosave_due, crc32, create_db_dir, db_autosave, db_close, db_open, db_path, decode_header,
+        deserialize_row, do_meta_command, encode_header, execute_select, execute_statement,
+        get_page, initialize_leaf_node, internal_node_child, internal_node_num_keys, is_node_root,
+        is_valid_db_name, leaf_node_num_cells, leaf_node_value_mut, node_type, page_offset,
+        prepare_statement, print_prompt, process_input, read_db_name, read_input, recover_file,
+        resolve_db_name, run_input, serialize_row, set_leaf_node_key, set_leaf_node_num_cells,
+        stamp_checksum, startup_banner, strip_comment, truncate_to_char_boundary, CliOptions,
+        Computed, Cursor, Error, ExecuteResult, InputBuffer, LogLevel, MetaCommandResult, NodeType,
+        OutputMode, Pager, PagerMetrics, PrepareResult, QueryResult, RawRow, Row, RowCache, Schema,
+        Session, Statement, Storage, Table, TableConfig, TextColumn, EMAIL_OFFSET, EMAIL_SIZE,
+        HEADER_SIZE, LOG_OUTPUT, PAGE_SIZE, PAGE_USABLE_SIZE, ROWS_PER_PAGE, ROW_SIZE,
+        TABLE_MAX_PAGES, TABLE_MAX_ROWS, USERNAME_MAX_LENGTH,
+    };
+
+    /// An empty users table kept in memory, never backed by a file.
+    fn memory_table() -> Table {
+        Table::from_bytes(&[]).unwrap()
+    }
+
+    /// A directory of the test's own under the system temp dir, removed when dropped, so
+    /// tests can't share files and leave none behind when an assertion fails. Its paths
+    /// are absolute, so they're opened in place instead of in the db directory.
+    struct TestDir(std::path::PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("try-db-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TestDir(dir)
+        }
+        fn path(&self, file_name: &str) -> String {
+            self.0.join(file_name).to_str().unwrap().to_owned()
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn test_session() -> Session<Vec<u8>> {
+        Session::new(memory_table(), Vec::new())
+    }
+
+    fn take_output(session: &mut Session<Vec<u8>>) -> String {
+        String::from_utf8(std::mem::take(&mut session.out)).unwrap()
+    }
+
+    fn run(session: &mut Session<Vec<u8>>, command: &str) -> Result<(), Error> {
+        let mut input_buffer = InputBuffer::new();
+        input_buffer.buffer_length = command.len() as i32;
+        input_buffer.buffer = Some(command.to_string());
+        run_input(&mut input_buffer, session)
+    }
+
+    fn insert_rows(session: &mut Session<Vec<u8>>, count: usize) {
+        for i in 0..count {
+            run(session, &format!("insert {} bala bala@gmail.com", i)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_inserting_and_retrieving_a_row() {
+        let table = memory_table();
+        let mut session = Session::new(table, Vec::new());
+        let mut input_buffer = InputBuffer::new();
+        let str = String::from("insert 1 bala bala@gmail.com");
+        input_buffer.buffer_length = str.len() as i32;
+        input_buffer.buffer = Some(str);
+        let _ = process_input(&mut input_buffer, &mut session);
+        assert_eq!(session.table.num_rows, 1);
+    }
+
+    #[test]
+    fn test_table_full() {
+        let cap = 5;
+        let table = memory_table().with_max_rows(cap);
+        let mut session = Session::new(table, Vec::new());
+        for i in 0..cap {
+            let res = run(&mut session, &format!("insert {} bala bala@gmail.com", i));
+            assert!(res.is_ok(), "insert {} failed with {:?}", i, res);
+        }
+        let res = run(&mut session, "insert 99 bala bala@gmail.com");
+        assert!(matches!(res, Err(Error::TableFull)));
+        assert_eq!(session.table.num_rows, cap);
+        assert_eq!(
+            memory_table().with_max_rows(usize::MAX).max_rows,
+            TABLE_MAX_ROWS
+        );
+    }
+
+    #[test]
+    fn allows_inserting_strings_with_maximum_length() {
+        let long_username = "a".repeat(33);
+        let long_email = "a".repeat(255);
+        let table = memory_table();
+        let mut session = Session::new(table, Vec::new());
+        let mut input_buffer = InputBuffer::new();
+        let str = format!("insert 1 {} {}", long_username, long_email);
+        input_buffer.buffer_length = str.len() as i32;
+        input_buffer.buffer = Some(str);
+        let res = process_input(&mut input_buffer, &mut session);
+        assert!(matches!(res, Err(Error::PrepareStringTooLong)));
+    }
+
+    #[test]
     fn allows_inserting_negative_id() {
         let long_username = "a".to_string();
         let long_email = "b".to_string();
-        let table = Table::new();
-        let mut cursor = Cursor::new(table);
+        let table = memory_table();
+        let mut session = Session::new(table, Vec::new());
+        let mut input_buffer = InputBuffer::new();
+        let str = format!("insert -10 {} {}", long_username, long_email);
+        input_buffer.buffer_length = str.len() as i32;
+        input_buffer.buffer = Some(str);
+        let res = process_input(&mut input_buffer, &mut session);
+        assert!(matches!(res, Err(Error::PrepareNegativeId)));
+    }
+    #[test]
+    fn testing_the_time_to_get_the_email() {
+        let table = memory_table();
+        let mut input_buffer = InputBuffer::new();
+        let mut session = Session::new(table, Vec::new());
+        for i in 0..1399 {
+            let str = format!("insert {} bala {}@gmail.com", i, i as f64 * 1e9 + 7f64);
+            input_buffer.buffer_length = str.len() as i32;
+            input_buffer.buffer = Some(str);
+            let _ = process_input(&mut input_buffer, &mut session);
+        }
+        let str = format!("select {}@gmail.com", 1388f64 * 1e9 + 7f64);
+        input_buffer.buffer_length = str.len() as i32;
+        input_buffer.buffer = Some(str);
+        let _ = process_input(&mut input_buffer, &mut session);
+    }
+
+    #[test]
+    fn save_and_load_round_trip_an_in_memory_table() {
+        let dir = TestDir::new("save-and-load");
+        let mut session = test_session();
+        session.force = true;
+        insert_rows(&mut session, 3);
+        run(&mut session, &format!(".save {}", dir.path("saved"))).unwrap();
+        run(&mut session, ".clear").unwrap();
+        run(&mut session, &format!(".load {}", dir.path("saved"))).unwrap();
+        take_output(&mut session);
+
+        run(&mut session, ".mode list").unwrap();
+        run(&mut session, "select").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "0|bala|bala@gmail.com\n1|bala|bala@gmail.com\n2|bala|bala@gmail.com\n"
+        );
+        run(&mut session, &format!(".load {}", dir.path("missing"))).unwrap();
+        assert!(take_output(&mut session).starts_with("Load failed"));
+        assert_eq!(session.table.num_rows, 3);
+    }
+
+    #[test]
+    fn a_file_in_place_of_the_db_directory_is_reported() {
+        let dir = TestDir::new("not-a-dir");
+        let path = dir.path("db");
+        std::fs::write(&path, b"").unwrap();
+        let res = create_db_dir(Path::new(&path));
+        assert!(matches!(res, Err(Error::DbDirNotADirectory)));
+    }
+
+    #[test]
+    fn the_prompt_can_be_changed() {
+        let mut session = test_session();
+        print_prompt(&session.prompt, &mut session.out);
+        assert_eq!(take_output(&mut session), "db -> ");
+
+        run(&mut session, ".prompt 'sql> ' '...> '").unwrap();
+        print_prompt(&session.prompt, &mut session.out);
+        assert_eq!(take_output(&mut session), "sql> ");
+        assert_eq!(session.continuation_prompt, "...> ");
+    }
+
+    #[test]
+    fn save_replaces_the_target_with_a_complete_db() {
+        let dir = TestDir::new("atomic-save");
+        let path = dir.path("atomic_save");
+        std::fs::write(&path, b"not a db").unwrap();
+        let mut session = test_session();
+        insert_rows(&mut session, 20);
+        run(&mut session, &format!(".save {}", path)).unwrap();
+
+        let saved = db_open(&path, &TableConfig::new()).map(|table| table.num_rows);
+        assert_eq!(saved.unwrap(), 20);
+        assert!(!std::path::Path::new(&dir.path("atomic_save.tmp")).exists());
+    }
+
+    #[test]
+    fn history_lists_the_last_commands_entered() {
+        let mut session = test_session();
+        for command in ["insert 1 a a@x.com", "select", ".changes", ".history"] {
+            run(&mut session, command).unwrap();
+        }
+        take_output(&mut session);
+
+        run(&mut session, ".history 2").unwrap();
+        assert_eq!(take_output(&mut session), "select\n.changes\n");
+    }
+
+    #[test]
+    fn autosave_is_due_once_the_interval_passes() {
+        assert!(!autosave_due(0, Duration::from_secs(3600)));
+        assert!(!autosave_due(30, Duration::from_secs(29)));
+        assert!(autosave_due(30, Duration::from_secs(30)));
+        assert!(autosave_due(30, Duration::from_secs(31)));
+    }
+
+    #[test]
+    fn the_banner_is_left_out_when_quiet() {
+        let options = CliOptions::parse(["--quiet".to_string()]).unwrap();
+        assert!(options.quiet);
+        let mut session = test_session();
+        insert_rows(&mut session, 2);
+
+        let table = &session.table;
+        assert_eq!(startup_banner("users.db", table, true, true), None);
+        assert_eq!(startup_banner("users.db", table, false, false), None);
+        assert_eq!(
+            startup_banner("users.db", table, false, true).unwrap(),
+            format!(
+                "try-db v{} — database 'users.db' (2 rows)",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    #[test]
+    fn empty_db_names_are_asked_for_again() {
+        let mut out = Vec::new();
+        let mut input = std::io::Cursor::new(b"\n  \nusers.db\n".to_vec());
+        assert_eq!(read_db_name(&mut input, &mut out).unwrap(), "users.db");
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "The database name can't be empty, enter a name: ".repeat(2)
+        );
+        let err = read_db_name(&mut "\n".as_bytes(), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn db_name_is_read_from_env_without_consuming_stdin() {
+        let dir = TestDir::new("env-name");
+        let env_name = dir.path("env-name.db");
+        let mut stdin = std::io::Cursor::new(b"stdin-name.db\n".to_vec());
+        let db_name = resolve_db_name(None, Some(env_name.clone()), &mut stdin).unwrap();
+        let from_arg = resolve_db_name(
+            Some("arg-name.db".to_string()),
+            Some(env_name.clone()),
+            &mut stdin,
+        )
+        .unwrap();
+
+        assert_eq!(db_name, env_name);
+        assert_eq!(from_arg, "arg-name.db");
+        assert_eq!(stdin.fill_buf().unwrap(), b"stdin-name.db\n");
+        let table = db_open(&db_name, &TableConfig::new()).unwrap();
+        assert_eq!(table.num_rows, 0);
+        assert!(std::path::Path::new(&env_name).exists());
+    }
+
+    #[test]
+    fn pragma_page_count_includes_the_pages_a_split_takes() {
+        let mut session = test_session();
+        assert_eq!(session.table.page_count(), 0);
+        insert_rows(&mut session, ROWS_PER_PAGE);
+        assert_eq!(session.table.page_count(), 1);
+        // Splitting the root leaf takes a page for each half.
+        run(&mut session, "insert 9999 bala bala@gmail.com").unwrap();
+        assert_eq!(session.table.page_count(), 3);
+        assert!(run(&mut session, ".pragma page_count").is_ok());
+        assert!(run(&mut session, ".pragma page_size").is_ok());
+        assert_eq!(session.table.num_rows, ROWS_PER_PAGE + 1);
+    }
+
+    #[test]
+    fn unrecognized_statement_never_reaches_execute() {
+        let mut session = test_session();
+        let res = run(&mut session, "foobar 1 bala bala@gmail.com");
+        assert!(matches!(res, Err(Error::PrepareUnrecognizedStatement)));
+        let res = run(&mut session, "ins");
+        assert!(matches!(res, Err(Error::PrepareUnrecognizedStatement)));
+        assert_eq!(session.table.num_rows, 0);
+    }
+
+    #[test]
+    fn select_on_an_empty_table_says_there_are_no_rows() {
+        let mut session = test_session();
+        run(&mut session, "select").unwrap();
+        assert_eq!(take_output(&mut session), "(0 rows)\n");
+
+        session.json = true;
+        session.format.mode = OutputMode::Json;
+        run(&mut session, "select").unwrap();
+        assert_eq!(take_output(&mut session), "");
+    }
+
+    #[test]
+    fn seed_appends_numbered_rows_after_the_largest_id() {
+        let mut session = test_session();
+        insert_rows(&mut session, 3);
+        run(&mut session, ".seed 10").unwrap();
+        assert_eq!(take_output(&mut session), "Seeded 10 rows\n");
+        assert_eq!(session.table.num_rows, 13);
+
+        run(&mut session, ".mode list").unwrap();
+        run(&mut session, "select rowid 3").unwrap();
+        assert_eq!(take_output(&mut session), "3|user3|user3@example.com\n");
+    }
+
+    #[test]
+    fn bulk_insert_writes_rows_without_parsing() {
+        let mut table = memory_table();
+        let rows = (0..500).map(|i| {
+            Row::from_parts(i, format!("user{}", i), format!("user{}@example.com", i)).unwrap()
+        });
+        assert_eq!(table.bulk_insert(rows).unwrap(), 500);
+        assert_eq!(table.num_rows, 500);
+
+        let mut row = Row::new();
+        deserialize_row(table.row_slot(421).unwrap(), &mut row, 0);
+        assert_eq!(row.id(), 421);
+        assert_eq!(row.username(), "user421");
+        assert_eq!(row.email(), "user421@example.com");
+    }
+
+    #[test]
+    fn opening_a_file_with_a_partial_row_reports_corruption() {
+        let dir = TestDir::new("corrupt-length");
+        let path = dir.path("corrupt-length.db");
+        std::fs::write(&path, [1u8; 100]).unwrap();
+        let res = db_open(&path, &TableConfig::new());
+        assert!(matches!(res, Err(Error::DbCorruptFile)));
+    }
+
+    #[test]
+    fn timer_output_follows_the_timer_toggle() {
+        let mut session = test_session();
+        run(&mut session, ".timer on").unwrap();
+        take_output(&mut session);
+        run(&mut session, "insert 1 bala bala@gmail.com").unwrap();
+        assert!(take_output(&mut session).contains("It took"));
+
+        run(&mut session, ".timer off").unwrap();
+        run(&mut session, "insert 2 bala bala@gmail.com").unwrap();
+        assert!(!take_output(&mut session).contains("It took"));
+    }
+
+    #[test]
+    fn echo_prints_each_command_before_running_it() {
+        let mut session = test_session();
+        run(&mut session, ".echo on").unwrap();
+        run(&mut session, "insert 1 bala bala@gmail.com").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "-- executing: insert 1 bala bala@gmail.com\n"
+        );
+
+        run(&mut session, ".echo off").unwrap();
+        take_output(&mut session);
+        run(&mut session, "select").unwrap();
+        assert!(!take_output(&mut session).contains("-- executing"));
+    }
+
+    #[test]
+    fn read_runs_each_line_of_a_script() {
+        let dir = TestDir::new("read-script");
+        let path = dir.path("read_script.sql");
+        std::fs::write(
+            &path,
+            "insert 1 bala bala@gmail.com\nbogus\n\ninsert 2 anu anu@gmail.com\n",
+        )
+        .unwrap();
+        let mut session = test_session();
+        run(&mut session, ".mode list").unwrap();
+
+        run(&mut session, &format!(".read {}", path)).unwrap();
+        assert!(take_output(&mut session).contains("Error on line 2"));
+        assert_eq!(session.table.num_rows, 2);
+        run(&mut session, "select").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "1|bala|bala@gmail.com\n2|anu|anu@gmail.com\n"
+        );
+
+        std::fs::write(
+            &path,
+            "insert 3 cat cat@gmail.com\nbogus\ninsert 4 dan dan@gmail.com\n",
+        )
+        .unwrap();
+        run(&mut session, &format!(".read {} --stop-on-error", path)).unwrap();
+        assert_eq!(session.table.num_rows, 3);
+    }
+
+    #[test]
+    fn read_skips_blank_and_comment_lines() {
+        let dir = TestDir::new("read-comments");
+        let path = dir.path("read_comments.sql");
+        std::fs::write(
+            &path,
+            "-- seed data\n\n   \ninsert 1 bala bala@gmail.com -- first\n  -- indented\n\t\ninsert 2 anu anu@gmail.com\n",
+        )
+        .unwrap();
+        let mut session = test_session();
+        run(&mut session, ".echo on").unwrap();
+
+        run(&mut session, &format!(".read {}", path)).unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            format!(
+                "-- executing: .read {}\n\
+                 -- executing: insert 1 bala bala@gmail.com\n\
+                 -- executing: insert 2 anu anu@gmail.com\n",
+                path
+            )
+        );
+        assert_eq!(session.table.num_rows, 2);
+    }
+
+    #[test]
+    fn full_line_comments_are_ignored() {
+        let mut session = test_session();
+        assert!(run(&mut session, "-- insert 1 bala bala@gmail.com").is_ok());
+        assert_eq!(session.table.num_rows, 0);
+        assert_eq!(take_output(&mut session), "");
+    }
+
+    #[test]
+    fn trailing_comments_are_stripped_before_parsing() {
+        let mut session = test_session();
+        run(&mut session, "insert 1 bala bala@gmail.com -- first user").unwrap();
+        let mut row = Row::new();
+        deserialize_row(session.table.row_slot(0).unwrap(), &mut row, 0);
+        assert_eq!(row.email(), "bala@gmail.com");
+        assert_eq!(
+            strip_comment("insert 1 'a--b' x -- note"),
+            "insert 1 'a--b' x "
+        );
+    }
+
+    #[test]
+    fn changes_reports_rows_touched_by_the_last_write() {
+        let mut session = test_session();
+        run(&mut session, ".changes").unwrap();
+        assert_eq!(take_output(&mut session), "0\n");
+
+        run(&mut session, "insert 1 bala bala@gmail.com").unwrap();
+        run(&mut session, "select").unwrap();
+        take_output(&mut session);
+        run(&mut session, ".changes").unwrap();
+        assert_eq!(take_output(&mut session), "1\n");
+
+        run(&mut session, "insert 2 anu anu@gmail.com").unwrap();
+        run(&mut session, "delete 1").unwrap();
+        take_output(&mut session);
+        run(&mut session, ".changes").unwrap();
+        assert_eq!(take_output(&mut session), "1\n");
+
+        assert!(matches!(
+            run(&mut session, "delete 7"),
+            Err(Error::NotFound)
+        ));
+        take_output(&mut session);
+        run(&mut session, ".changes").unwrap();
+        assert_eq!(take_output(&mut session), "0\n");
+    }
+
+    fn write_fixture(file_name: &str, rows: u32) {
+        let mut table = db_open(file_name, &TableConfig::new()).unwrap();
+        let rows = (0..rows).map(|i| {
+            Row::from_parts(i, format!("user{}", i), format!("user{}@example.com", i)).unwrap()
+        });
+        table.bulk_insert(rows).unwrap();
+        db_close(&mut table).unwrap();
+    }
+
+    #[test]
+    fn a_partially_written_last_row_is_dropped() {
+        let dir = TestDir::new("partial-row");
+        let path = dir.path("partial-row.db");
+        write_fixture(&path, 3);
+        let mut bytes = std::fs::read(&path).unwrap();
+        let email = HEADER_SIZE + Schema::Users.layout().value_offset(2) + EMAIL_OFFSET;
+        bytes[email..email + EMAIL_SIZE].fill(0);
+
+        // Torn before its page footer was refreshed: only `.recover` can read it.
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(recover_file(&path, Schema::Users).unwrap(), 2);
+        assert_eq!(db_open(&path, &TableConfig::new()).unwrap().num_rows, 2);
+
+        // Torn with a matching footer: opening drops it straight away.
+        let page = &mut bytes[HEADER_SIZE..HEADER_SIZE + PAGE_SIZE];
+        stamp_checksum(page.try_into().unwrap());
+        std::fs::write(&path, &bytes).unwrap();
+        let mut table = db_open(&path, &TableConfig::new()).unwrap();
+        assert_eq!(table.num_rows, 2);
+        // The shortened leaf reaches the file even when nothing else touches it.
+        db_close(&mut table).unwrap();
+        let mut table = db_open(&path, &TableConfig::new()).unwrap();
+        assert_eq!(table.num_rows, 2);
+        assert_eq!(table.count_stored_rows().unwrap(), 2);
+    }
+
+    #[test]
+    fn num_rows_is_kept_in_the_header() {
+        let dir = TestDir::new("header-rows");
+        let path = dir.path("header-rows.db");
+        let num_rows = 2 * ROWS_PER_PAGE + 5;
+        write_fixture(&path, num_rows as u32);
+        let bytes = std::fs::read(&path).unwrap();
+        let header = bytes[..HEADER_SIZE].try_into().unwrap();
+        assert_eq!(decode_header(header).unwrap(), (Schema::Users, num_rows, 0));
+
+        let mut table = db_open(&path, &TableConfig::new()).unwrap();
+        assert_eq!(table.num_rows, num_rows);
+        // The count came from the header, without loading the leaves.
+        assert_eq!(table.pager.resident_pages(), 0);
+        assert_eq!(table.count_stored_rows().unwrap(), num_rows);
+    }
+
+    #[test]
+    fn autosaved_rows_survive_a_table_that_is_never_closed() {
+        let dir = TestDir::new("autosave");
+        let path = dir.path("autosave.db");
+        let num_rows = 2 * ROWS_PER_PAGE + 5;
+        let mut table = db_open(&path, &TableConfig::new()).unwrap();
+        let rows = (0..num_rows as u32).map(|i| {
+            Row::from_parts(i, format!("user{}", i), format!("user{}@example.com", i)).unwrap()
+        });
+        table.bulk_insert(rows).unwrap();
+        assert!(db_autosave(&mut table).unwrap().is_empty());
+        // Dropped as a crash would leave it, without `db_close`.
+        drop(table);
+
+        let mut table = db_open(&path, &TableConfig::new()).unwrap();
+        let verified = table.count_stored_rows();
+        assert_eq!(table.num_rows, num_rows);
+        assert_eq!(verified.unwrap(), num_rows);
+        assert_eq!(
+            table.read_row(num_rows - 1).unwrap().id(),
+            num_rows as u32 - 1
+        );
+    }
+
+    #[test]
+    fn ranged_flush_matches_full_page_flush() {
+        let dir = TestDir::new("ranged-flush");
+        let ranged_path = dir.path("ranged-flush.db");
+        let full_path = dir.path("full-flush.db");
+        let extra_row =
+            || Row::from_parts(3, "late".to_string(), "late@example.com".to_string()).unwrap();
+        write_fixture(&ranged_path, 3);
+        write_fixture(&full_path, 3);
+
+        let mut ranged = db_open(&ranged_path, &TableConfig::new()).unwrap();
+        ranged.bulk_insert(std::iter::once(extra_row())).unwrap();
+        // The new cell and the leaf header counting it.
+        let cell_end = Schema::Users.layout().cell_offset(4);
+        assert_eq!(ranged.pager.dirty[0], Some(0..cell_end));
+        db_close(&mut ranged).unwrap();
+
+        let mut full = db_open(&full_path, &TableConfig::new()).unwrap();
+        full.bulk_insert(std::iter::once(extra_row())).unwrap();
+        full.pager.mark_dirty(0, 0..PAGE_USABLE_SIZE);
+        assert!(full.pager.flush_dirty_pages(0..1).is_empty());
+        let header = encode_header(full.schema, full.num_rows, full.fill);
+        full.pager.write_header(&header).unwrap();
+
+        let ranged_bytes = std::fs::read(&ranged_path).unwrap();
+        let full_bytes = std::fs::read(&full_path).unwrap();
+        assert_eq!(ranged_bytes, full_bytes);
+        assert_eq!(ranged_bytes.len(), HEADER_SIZE + PAGE_SIZE);
+    }
+
+    #[test]
+    fn flushing_at_default_verbosity_logs_nothing() {
+        let dir = TestDir::new("quiet-flush");
+        let path = dir.path("quiet-flush.db");
+        write_fixture(&path, 2);
+        assert_eq!(LOG_OUTPUT.with(|log| log.borrow().clone()), "");
+    }
+
+    #[test]
+    fn verbose_flags_raise_the_log_level() {
+        let parse =
+            |args: &[&str]| CliOptions::parse(args.iter().map(|arg| arg.to_string())).unwrap();
+        let options = parse(&["users.db", "-v"]);
+        assert_eq!(options.db_name.as_deref(), Some("users.db"));
+        assert_eq!(options.log_level(), LogLevel::Info);
+        assert_eq!(parse(&["-vv"]).log_level(), LogLevel::Debug);
+        assert_eq!(parse(&["--verbose", "--verbose", "-v"]).verbose, 3);
+    }
+
+    #[test]
+    fn fill_bytes_are_limited_to_bytes_no_value_can_hold() {
+        let parse = |value: &str| {
+            CliOptions::parse(["--fill-byte".to_string(), value.to_string()])
+                .map(|options| options.fill_byte)
+        };
+        assert_eq!(parse("0xff"), Ok(0xff));
+        assert_eq!(parse("1"), Ok(1));
+        assert!(parse("97").is_err());
+        assert!(parse("0xa9").is_err());
+
+        assert!(matches!(
+            Row::from_parts(1, "ba\u{1}la", "bala@gmail.com"),
+            Err(PrepareResult::PrepareSyntaxError)
+        ));
+    }
+
+    #[test]
+    fn free_rows_shrinks_to_zero_at_capacity() {
+        assert_eq!(memory_table().free_rows(), TABLE_MAX_ROWS);
+        let mut session = Session::new(memory_table().with_max_rows(50), Vec::new());
+        run(&mut session, "insert 1 bala bala@gmail.com").unwrap();
+        assert_eq!(session.table.free_rows(), 49);
+
+        let rows = (2..)
+            .map(|i| Row::from_parts(i, "bala".to_string(), "bala@gmail.com".to_string()).unwrap());
+        session.table.bulk_insert(rows).unwrap();
+        assert_eq!(session.table.free_rows(), 0);
+        run(&mut session, ".stat").unwrap();
+        assert!(take_output(&mut session).contains("free rows: 0"));
+    }
+
+    #[test]
+    fn bytes_used_counts_the_header_and_each_row() {
+        let mut session = test_session();
+        assert_eq!(session.table.bytes_used(), HEADER_SIZE as u64);
+        insert_rows(&mut session, 20);
+        assert_eq!(
+            session.table.bytes_used(),
+            (HEADER_SIZE + 20 * ROW_SIZE) as u64
+        );
+        run(&mut session, ".stat").unwrap();
+        let stat = take_output(&mut session);
+        assert!(stat.contains(&format!("bytes used: {}", HEADER_SIZE + 20 * ROW_SIZE)));
+        assert!(stat.contains("bytes allocated: "));
+    }
+
+    #[test]
+    fn select_rowid_prints_the_row_at_that_position() {
+        let mut session = test_session();
+        run(&mut session, "insert 7 first first@gmail.com").unwrap();
+        run(&mut session, "insert 3 second second@gmail.com").unwrap();
+        take_output(&mut session);
+
+        // Rows are stored in id order, so the later insert takes row 0.
+        run(&mut session, "select rowid 0").unwrap();
+        assert!(take_output(&mut session).starts_with("Row 0 Row { id: 3,"));
+        run(&mut session, "select rowid 1").unwrap();
+        assert!(take_output(&mut session).starts_with("Row 1 Row { id: 7,"));
+        run(&mut session, "select rowid 2").unwrap();
+        assert_eq!(take_output(&mut session), "(0 rows)\n");
+    }
+
+    #[test]
+    fn overflowing_the_root_leaf_splits_it_under_an_internal_root() {
+        let mut session = test_session();
+        run(&mut session, ".mode list").unwrap();
+        // Descending ids, so every insert lands in front of the rows already stored.
+        for id in (0..=ROWS_PER_PAGE).rev() {
+            run(
+                &mut session,
+                &format!("insert {} user{} user{}@x.com", id, id, id),
+            )
+            .unwrap();
+        }
+
+        let root = *get_page(&mut session.table.pager, 0).unwrap();
+        assert_eq!(node_type(&root), Some(NodeType::NodeInternal));
+        assert!(is_node_root(&root));
+        assert_eq!(internal_node_num_keys(&root), 1);
+        let layout = Schema::Users.layout();
+        let left_count = layout.left_split_count();
+        let children = [internal_node_child(&root, 0), internal_node_child(&root, 1)];
+        for (child, cells) in children
+            .into_iter()
+            .zip([left_count, ROWS_PER_PAGE + 1 - left_count])
+        {
+            let page = get_page(&mut session.table.pager, child).unwrap();
+            assert_eq!(node_type(page), Some(NodeType::NodeLeaf));
+            assert!(!is_node_root(page));
+            assert_eq!(leaf_node_num_cells(page), cells);
+        }
+
+        run(&mut session, "select").unwrap();
+        let ids: Vec<usize> = take_output(&mut session)
+            .lines()
+            .map(|line| line.split('|').next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(ids, (0..=ROWS_PER_PAGE).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn split_leaves_join_the_root_and_survive_a_reopen() {
+        let dir = TestDir::new("split-reopen");
+        let path = dir.path("split-reopen.db");
+        write_fixture(&path, 4 * ROWS_PER_PAGE as u32);
+        let mut table = db_open(&path, &TableConfig::new()).unwrap();
+        assert_eq!(table.num_rows, 4 * ROWS_PER_PAGE);
+        assert_eq!(table.count_stored_rows().unwrap(), table.num_rows);
+        let leaves = table.leaf_pages().unwrap();
+        let root = get_page(&mut table.pager, 0).unwrap();
+        assert_eq!(internal_node_num_keys(root) + 1, leaves.len());
+        assert!(leaves.len() > 2);
+        for row_num in 0..table.num_rows {
+            assert_eq!(table.read_row(row_num).unwrap().id() as usize, row_num);
+        }
+    }
+
+    #[test]
+    fn find_descends_to_existing_and_missing_keys_in_a_two_level_tree() {
+        let mut table = memory_table();
+        let count = 3 * ROWS_PER_PAGE as u32;
+        let rows = (0..count).map(|i| Row::from_parts(i * 2, "bala", "bala@gmail.com").unwrap());
+        table.bulk_insert(rows).unwrap();
+        let root = get_page(&mut table.pager, 0).unwrap();
+        assert_eq!(node_type(root), Some(NodeType::NodeInternal));
+
+        for row_num in 0..count as usize {
+            let key = row_num as u32 * 2;
+            let mut cursor = Cursor::find(&mut table, key).unwrap();
+            assert_eq!(cursor.row_num, row_num);
+            assert_eq!(cursor.cursor_row().unwrap().id(), key);
+            // A missing key lands on the next larger one.
+            let mut cursor = Cursor::find(&mut table, key + 1).unwrap();
+            if row_num + 1 < count as usize {
+                assert_eq!(cursor.row_num, row_num + 1);
+                assert_eq!(cursor.cursor_row().unwrap().id(), key + 2);
+            } else {
+                assert!(cursor.end_of_table);
+            }
+        }
+    }
+
+    #[test]
+    fn find_searches_a_root_leaf() {
+        let mut session = test_session();
+        assert!(Cursor::find(&mut session.table, 5).unwrap().end_of_table);
+        for id in [2, 4, 6] {
+            run(&mut session, &format!("insert {} bala bala@gmail.com", id)).unwrap();
+        }
+        let mut cursor = Cursor::find(&mut session.table, 4).unwrap();
+        assert_eq!((cursor.row_num, cursor.cursor_row().unwrap().id()), (1, 4));
+        assert_eq!(Cursor::find(&mut session.table, 1).unwrap().row_num, 0);
+        assert!(Cursor::find(&mut session.table, 7).unwrap().end_of_table);
+    }
+
+    #[test]
+    fn btree_prints_each_node_indented_under_its_parent() {
+        let mut session = test_session();
+        run(&mut session, ".btree").unwrap();
+        assert_eq!(take_output(&mut session), "Tree:\n(empty)\n");
+
+        for id in 1..=ROWS_PER_PAGE + 1 {
+            run(&mut session, &format!("insert {} bala bala@gmail.com", id)).unwrap();
+        }
+        run(&mut session, ".btree").unwrap();
+        let leaf = |page_num: usize, keys: std::ops::RangeInclusive<usize>| {
+            let mut lines = format!(
+                "  - leaf (page {}, size {})\n",
+                page_num,
+                keys.clone().count()
+            );
+            for key in keys {
+                lines += &format!("    - {}\n", key);
+            }
+            lines
+        };
+        // The old root moved to page 2 as the left leaf; page 1 took the upper half.
+        let expected = format!(
+            "Tree:\n- internal (page 0, size 1)\n{}  - key 7\n{}",
+            leaf(2, 1..=7),
+            leaf(1, 8..=14)
+        );
+        assert_eq!(take_output(&mut session), expected);
+    }
+
+    #[test]
+    fn btree_pages_lists_resident_pages_until_they_are_flushed() {
+        let mut session = test_session();
+        run(&mut session, ".btree pages").unwrap();
+        assert_eq!(take_output(&mut session), "(no pages loaded)\n");
+
+        insert_rows(&mut session, ROWS_PER_PAGE + 1);
+        take_output(&mut session);
+        run(&mut session, ".btree pages").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            format!(
+                "page 0: internal, 1 keys (dirty)\n\
+                 page 1: leaf, {} rows (dirty)\n\
+                 page 2: leaf, {} rows (dirty)\n",
+                ROWS_PER_PAGE + 1 - ROWS_PER_PAGE.div_ceil(2),
+                ROWS_PER_PAGE.div_ceil(2)
+            )
+        );
+
+        db_close(&mut session.table).unwrap();
+        run(&mut session, ".btree pages").unwrap();
+        assert_eq!(take_output(&mut session), "(no pages loaded)\n");
+    }
+
+    #[test]
+    fn rows_inserted_out_of_order_read_back_sorted() {
+        let mut session = test_session();
+        run(&mut session, ".mode list").unwrap();
+        for id in [5, 1, 3] {
+            run(
+                &mut session,
+                &format!("insert {} user{} user{}@x.com", id, id, id),
+            )
+            .unwrap();
+        }
+        run(&mut session, "select").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "1|user1|user1@x.com\n3|user3|user3@x.com\n5|user5|user5@x.com\n"
+        );
+
+        let res = run(&mut session, "insert 3 again again@x.com");
+        assert!(matches!(res, Err(Error::DuplicateKey)));
+        assert_eq!(take_output(&mut session), "Error: Duplicate key.\n");
+        assert_eq!(session.table.num_rows, 3);
+    }
+
+    #[test]
+    fn lookups_read_only_the_pages_on_their_path() {
+        let dir = TestDir::new("descent");
+        let path = dir.path("descent.db");
+        let num_rows = 5 * ROWS_PER_PAGE;
+        write_fixture(&path, num_rows as u32);
+        let mut table = db_open(&path, &TableConfig::new()).unwrap();
+        assert!(table.page_count() > 3);
+
+        let key = num_rows as u32 - 1;
+        let cursor = Cursor::find(&mut table, key).unwrap();
+        assert_eq!(cursor.row_num, num_rows - 1);
+        // The root and the leaf holding the key, none of the leaves before it.
+        assert_eq!(table.pager.metrics().pages_read, 2);
+        assert_eq!(table.read_row(num_rows - 2).unwrap().id(), key - 1);
+        assert_eq!(table.pager.metrics().pages_read, 2);
+
+        // Row numbers past a delete shift down through the root's counts.
+        let cursor = Cursor::find(&mut table, 0).unwrap();
+        let row_num = cursor.row_num;
+        table.delete_row(row_num).unwrap();
+        for row_num in 0..table.num_rows {
+            assert_eq!(table.read_row(row_num).unwrap().id() as usize, row_num + 1);
+        }
+        assert!(table.row_slot(table.num_rows).is_err());
+    }
+
+    #[test]
+    fn exit_quit_and_q_all_exit() {
+        let mut session = test_session();
+        for command in [".exit", ".quit", ".q"] {
+            assert!(matches!(
+                run(&mut session, command),
+                Err(Error::MetaCommandExit)
+            ));
+        }
+    }
+
+    #[test]
+    fn max_length_email_round_trips_with_its_terminator() {
+        let mut session = test_session();
+        let email = "e".repeat(EMAIL_SIZE - 1);
+        run(&mut session, &format!("insert 1 bala {}", email)).unwrap();
+        let res = run(
+            &mut session,
+            &format!("insert 2 bala {}", "e".repeat(EMAIL_SIZE)),
+        );
+        assert!(matches!(res, Err(Error::PrepareStringTooLong)));
+
+        let slot = session.table.row_slot(0).unwrap();
+        assert_eq!(slot[ROW_SIZE - 1], 0);
+        let mut row = Row::new();
+        deserialize_row(slot, &mut row, 0);
+        assert_eq!(row.email(), email);
+        assert_eq!(session.table.num_rows, 1);
+    }
+
+    #[test]
+    fn validate_reports_prepare_status_without_executing() {
+        let mut session = test_session();
+        run(&mut session, ".validate insert 1 a a@x.com").unwrap();
+        assert_eq!(take_output(&mut session), "valid\n");
+        run(&mut session, ".validate insert -1 a a@x.com").unwrap();
+        assert_eq!(take_output(&mut session), "invalid: PrepareNegativeId\n");
+        run(&mut session, ".validate insert 1 a").unwrap();
+        assert!(take_output(&mut session).ends_with("invalid: PrepareError\n"));
+        assert_eq!(session.table.num_rows, 0);
+    }
+
+    #[test]
+    fn read_input_treats_a_bare_crlf_as_empty() {
+        let mut input_buffer = InputBuffer::new();
+        read_input(&mut input_buffer, &mut "\r\n".as_bytes());
+        assert_eq!(input_buffer.buffer, None);
+
+        read_input(&mut input_buffer, &mut "select\r\n".as_bytes());
+        assert_eq!(input_buffer.buffer.as_deref(), Some("select"));
+        assert_eq!(input_buffer.input_length, 6);
+
+        read_input(&mut input_buffer, &mut "".as_bytes());
+        assert_eq!(input_buffer.buffer, None);
+    }
+
+    #[test]
+    fn explain_describes_an_insert_without_running_it() {
+        let mut session = test_session();
+        insert_rows(&mut session, 2);
+        take_output(&mut session);
+
+        run(&mut session, "explain insert 5 a a@x.com").unwrap();
+        let output = take_output(&mut session);
+        assert!(output.starts_with("StatementInsert\n"));
+        assert!(output.contains("target row: 2 (page 0, cell 2, byte offset 604)"));
+        assert_eq!(session.table.num_rows, 2);
+    }
+
+    #[test]
+    fn products_table_keeps_its_schema_across_reopen() {
+        let dir = TestDir::new("products-reopen");
+        let path = dir.path("products-reopen.db");
+        let table = Table::open_with_schema(&path, Schema::Products).unwrap();
+        let mut session = Session::new(table, Vec::new());
+        run(&mut session, "insert 1 pen 2.5").unwrap();
+        run(&mut session, "insert 2 notebook 12.75").unwrap();
+        assert!(matches!(
+            run(&mut session, "insert 3 pen cheap"),
+            Err(Error::PrepareError)
+        ));
+        db_close(&mut session.table).unwrap();
+
+        let table = db_open(&path, &TableConfig::new()).unwrap();
+        let mismatch = Table::open_with_schema(&path, Schema::Users);
+        assert!(matches!(mismatch, Err(Error::SchemaMismatch)));
+        assert_eq!(table.schema, Schema::Products);
+        assert_eq!(table.num_rows, 2);
+        let mut session = Session::new(table, Vec::new());
+        run(&mut session, "select").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "Row 0 Product { id: 1, name: \"pen\", price: 2.5 }\n\
+             Row 1 Product { id: 2, name: \"notebook\", price: 12.75 }\n"
+        );
+    }
+
+    #[test]
+    fn files_without_a_header_are_rejected() {
+        let dir = TestDir::new("headerless");
+        let path = dir.path("headerless.db");
+        std::fs::write(&path, [1u8; PAGE_SIZE]).unwrap();
+        let res = db_open(&path, &TableConfig::new());
+        assert!(matches!(res, Err(Error::DbUnsupportedFormat)));
+    }
+
+    #[test]
+    fn clear_empties_the_table_after_confirmation() {
+        let mut session = test_session().with_input(std::io::Cursor::new(b"n\ny\n".to_vec()));
+        insert_rows(&mut session, 3);
+        run(&mut session, ".clear").unwrap();
+        assert!(take_output(&mut session).ends_with("Clear cancelled\n"));
+        assert_eq!(session.table.num_rows, 3);
+
+        run(&mut session, ".clear").unwrap();
+        assert!(take_output(&mut session).ends_with("Cleared 3 rows\n"));
+        run(&mut session, "select").unwrap();
+        assert_eq!(take_output(&mut session), "(0 rows)\n");
+        assert_eq!(session.table.num_rows, 0);
+    }
+
+    #[test]
+    fn shrink_releases_pages_past_the_last_row() {
+        let mut session = test_session();
+        insert_rows(&mut session, ROWS_PER_PAGE);
+        // Stands in for pages read past the end of the tree.
+        get_page(&mut session.table.pager, 1).unwrap();
+        get_page(&mut session.table.pager, 2).unwrap();
+        assert_eq!(session.table.pager.resident_pages(), 3);
+
+        run(&mut session, ".shrink").unwrap();
+        assert_eq!(take_output(&mut session), "Released 2 pages\n");
+        assert_eq!(session.table.pager.resident_pages(), 1);
+        assert!(session.table.pager.file_length <= page_offset(1));
+    }
+
+    #[test]
+    fn forced_clear_truncates_the_file_on_close() {
+        let dir = TestDir::new("clear-truncate");
+        let path = dir.path("clear-truncate.db");
+        write_fixture(&path, 20);
+        let mut session = Session::new(db_open(&path, &TableConfig::new()).unwrap(), Vec::new());
+        session.force = true;
+        run(&mut session, ".clear").unwrap();
+        db_close(&mut session.table).unwrap();
+
+        let file_length = std::fs::metadata(&path).unwrap().len();
+        let reopened = db_open(&path, &TableConfig::new()).unwrap();
+        assert_eq!(file_length, HEADER_SIZE as u64);
+        assert_eq!(reopened.num_rows, 0);
+    }
+
+    #[test]
+    fn db_open_creates_an_empty_table_for_a_new_file() {
+        let dir = TestDir::new("fresh-open");
+        let path = dir.path("fresh-open.db");
+        let table = db_open(&path, &TableConfig::new()).unwrap();
+        let file_length = std::fs::metadata(&path).unwrap().len();
+        assert_eq!(table.num_rows, 0);
+        assert_eq!(table.schema, Schema::Users);
+        assert_eq!(file_length, HEADER_SIZE as u64);
+    }
+
+    #[test]
+    fn corrupted_page_fails_its_checksum_on_load() {
+        let dir = TestDir::new("checksum");
+        let path = dir.path("checksum.db");
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        write_fixture(&path, 3);
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[HEADER_SIZE + ROW_SIZE + 10] ^= 0x40;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mut table = db_open(&path, &TableConfig::new()).unwrap();
+        assert!(matches!(
+            get_page(&mut table.pager, 0),
+            Err(Error::ChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    fn db_names_with_path_separators_are_rejected() {
+        for name in ["../escape", "a/b", ".."] {
+            assert!(matches!(
+                db_open(name, &TableConfig::new()),
+                Err(Error::InvalidDbName)
+            ));
+        }
+        assert!(!std::path::Path::new("escape").exists());
+        assert!(!std::path::Path::new("db/a").exists());
+    }
+
+    #[test]
+    fn absolute_db_names_bypass_the_db_directory() {
+        let dir = TestDir::new("absolute");
+        let name = dir.path("absolute.db");
+        assert!(is_valid_db_name(&name));
+        assert_eq!(db_path(&name), Path::new(&name));
+        assert_eq!(db_path("relative.db"), Path::new("db/relative.db"));
+
+        db_open(&name, &TableConfig::new()).unwrap();
+        assert!(Path::new(&name).exists());
+    }
+
+    #[test]
+    fn batched_close_matches_page_by_page_flushes() {
+        let dir = TestDir::new("batched-flush");
+        let batched_path = dir.path("batched-flush.db");
+        let single_path = dir.path("single-flush.db");
+        let rows = || {
+            (0..3 * ROWS_PER_PAGE as u32).map(|i| {
+                Row::from_parts(i, format!("user{}", i), format!("user{}@example.com", i)).unwrap()
+            })
+        };
+        let mut batched = db_open(&batched_path, &TableConfig::new()).unwrap();
+        batched.bulk_insert(rows()).unwrap();
+        db_close(&mut batched).unwrap();
+
+        let mut single = db_open(&single_path, &TableConfig::new()).unwrap();
+        single.bulk_insert(rows()).unwrap();
+        for page_num in 0..single.page_count() {
+            assert!(single
+                .pager
+                .flush_dirty_pages(page_num..page_num + 1)
+                .is_empty());
+        }
+        let header = encode_header(single.schema, single.num_rows, single.fill);
+        single.pager.write_header(&header).unwrap();
+
+        let batched_bytes = std::fs::read(&batched_path).unwrap();
+        let single_bytes = std::fs::read(&single_path).unwrap();
+        assert_eq!(
+            batched_bytes.len(),
+            HEADER_SIZE + batched.page_count() * PAGE_SIZE
+        );
+        assert_eq!(batched_bytes, single_bytes);
+    }
+
+    #[test]
+    fn serialized_row_round_trips_unchanged() {
+        let expected =
+            Row::from_parts(42, "bala".to_string(), "bala@gmail.com".to_string()).unwrap();
+        let mut slot = [0u8; ROW_SIZE];
+        serialize_row(&expected, &mut slot, 0);
+        let mut actual = Row::new();
+        deserialize_row(&slot, &mut actual, 0);
+        assert_eq!(actual, expected.clone());
+    }
+
+    #[test]
+    fn column_mode_truncates_long_fields_for_display_only() {
+        let mut session = test_session();
+        let email = format!("{}@example.com", "e".repeat(40));
+        run(&mut session, &format!("insert 1 bala {}", email)).unwrap();
+        run(&mut session, ".mode column").unwrap();
+        run(&mut session, ".width 10").unwrap();
+        run(&mut session, "select").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "1           bala        eeeeeeeee…\n"
+        );
+
+        run(&mut session, ".mode json").unwrap();
+        run(&mut session, "select").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            format!(
+                "{{\"id\": 1, \"username\": \"bala\", \"email\": \"{}\"}}\n",
+                email
+            )
+        );
+        run(&mut session, ".mode list").unwrap();
+        run(&mut session, "select").unwrap();
+        assert_eq!(take_output(&mut session), format!("1|bala|{}\n", email));
+    }
+
+    /// In-memory storage whose reads fail once they reach `fail_from` and whose writes
+    /// fail when they touch `failing_writes`.
+    #[derive(Debug)]
+    struct FailingStorage {
+        inner: std::io::Cursor<Vec<u8>>,
+        fail_from: u64,
+        failing_writes: std::ops::Range<u64>,
+    }
+
+    impl Read for FailingStorage {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.inner.position() >= self.fail_from {
+                return Err(std::io::Error::other("injected read failure"));
+            }
+            self.inner.read(buf)
+        }
+    }
+
+    impl Write for FailingStorage {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let position = self.inner.position();
+            if position < self.failing_writes.end
+                && self.failing_writes.start < position + buf.len() as u64
+            {
+                return Err(std::io::Error::other("injected write failure"));
+            }
+            self.inner.write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    impl Seek for FailingStorage {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    impl Storage for FailingStorage {
+        fn set_len(&mut self, len: u64) -> std::io::Result<()> {
+            self.inner.set_len(len)
+        }
+        fn sync_all(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn select_stops_with_execute_fail_on_an_unreadable_page() {
+        let dir = TestDir::new("failing-page");
+        let path = dir.path("failing-page.db");
+        write_fixture(&path, 2 * ROWS_PER_PAGE as u32);
+        let bytes = std::fs::read(&path).unwrap();
+        let file_length = bytes.len() as u64;
+        let storage = FailingStorage {
+            inner: std::io::Cursor::new(bytes),
+            fail_from: page_offset(1),
+            failing_writes: 0..0,
+        };
+        let mut table = Table {
+            num_rows: 2 * ROWS_PER_PAGE,
+            schema: Schema::Users,
+            layout: Schema::Users.layout(),
+            max_rows: TABLE_MAX_ROWS,
+            append_only: false,
+            read_only: false,
+            pager: Pager::new(Box::new(storage), file_length),
+            file_name: None,
+            row_cache: RowCache::new(0),
+            fill: 0,
+        };
+        let res = execute_select(&Statement::new(), &mut table);
+        assert!(matches!(res, Err(ExecuteResult::ExecuteFail)));
+    }
+
+    #[test]
+    fn statements_return_what_they_produced() {
+        let mut session = test_session();
+        let mut execute = |text: &str| {
+            let mut input_buffer = InputBuffer::new();
+            input_buffer.buffer = Some(text.to_owned());
+            let mut statement = Statement::new();
+            prepare_statement(&input_buffer, &mut statement);
+            execute_statement(&statement, &mut session.table).unwrap()
+        };
+        let bala = Row::from_parts(1, "bala", "bala@gmail.com").unwrap();
+        let anu = Row::from_parts(2, "anu", "anu@gmail.com").unwrap();
+
+        assert_eq!(
+            execute("insert 1 bala bala@gmail.com"),
+            QueryResult::Inserted(1)
+        );
+        assert_eq!(
+            execute("insert 2 anu anu@gmail.com"),
+            QueryResult::Inserted(1)
+        );
+        assert_eq!(
+            execute("insert or replace 2 anu anu@gmail.com"),
+            QueryResult::Inserted(1)
+        );
+        assert_eq!(
+            execute("select"),
+            QueryResult::Selected(vec![(0, bala.clone()), (1, anu.clone())])
+        );
+        assert_eq!(
+            execute("select rowid 1"),
+            QueryResult::Selected(vec![(1, anu.clone())])
+        );
+        assert_eq!(
+            execute("select tail 1"),
+            QueryResult::Selected(vec![(1, anu.clone())])
+        );
+        assert_eq!(
+            execute("select anu@gmail.com"),
+            QueryResult::Found(Some((1, anu)))
+        );
+        assert_eq!(
+            execute("select distinct username"),
+            QueryResult::Usernames(vec!["bala".to_owned(), "anu".to_owned()])
+        );
+        assert_eq!(
+            execute("select len(username)"),
+            QueryResult::Computed(Computed::Len(TextColumn::Username), vec![(0, 4), (1, 3)])
+        );
+    }
+
+    #[test]
+    fn table_config_applies_its_options_on_open() {
+        let dir = TestDir::new("table-config");
+        let path = dir.path("table-config.db");
+        let table = TableConfig::new()
+            .read_only(true)
+            .durable(true)
+            .max_rows(5)
+            .open(&path)
+            .unwrap();
+        assert!(table.read_only);
+        assert!(!table.append_only);
+        assert!(table.pager.durable);
+        assert_eq!(table.max_rows, 5);
+        assert_eq!(table.schema, Schema::Users);
+    }
+
+    #[test]
+    fn close_writes_each_used_page_exactly_once() {
+        for (rows, pages) in [
+            (ROWS_PER_PAGE - 1, 1),
+            (ROWS_PER_PAGE, 1),
+            // One row past a full root leaf splits it into an internal root and two leaves.
+            (ROWS_PER_PAGE + 1, 3),
+        ] {
+            let mut session = test_session();
+            insert_rows(&mut session, rows);
+            assert!(db_close(&mut session.table).unwrap().is_empty());
+            assert_eq!(
+                session.table.pager.metrics().pages_written,
+                pages,
+                "{} rows",
+                rows
+            );
+            assert_eq!(session.table.pager.file_length, page_offset(pages as usize));
+        }
+    }
+
+    #[test]
+    fn close_reports_every_page_that_failed_to_flush() {
+        let storage = FailingStorage {
+            inner: std::io::Cursor::new(Vec::new()),
+            fail_from: u64::MAX,
+            failing_writes: page_offset(1)..page_offset(2),
+        };
+        let mut table = memory_table();
+        table.pager = Pager::new(Box::new(storage), 0);
+        table
+            .bulk_insert((0..4 * ROWS_PER_PAGE as u32).map(|i| {
+                Row::from_parts(i, format!("user{}", i), format!("user{}@example.com", i)).unwrap()
+            }))
+            .unwrap();
+
+        let failures = db_close(&mut table).unwrap();
+        let failed: Vec<usize> = failures.iter().map(|(page_num, _)| *page_num).collect();
+        assert_eq!(failed, [1]);
+        assert_eq!(failures[0].1.to_string(), "injected write failure");
+        let pages_written = table.pager.metrics().pages_written;
+        assert_eq!(pages_written, table.page_count() as u64 - 1);
+    }
+
+    #[test]
+    fn recover_rewrites_a_corrupt_header() {
+        let dir = TestDir::new("recover-header");
+        let path = dir.path("recover-header.db");
+        write_fixture(&path, 20);
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[8..16].fill(0xff);
+        std::fs::write(&path, bytes).unwrap();
+        assert!(matches!(
+            db_open(&path, &TableConfig::new()),
+            Err(Error::DbUnsupportedFormat)
+        ));
+
+        let mut session = test_session();
+        run(&mut session, &format!(".recover {}", path)).unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            format!("Recovered 20 rows in {:?}\n", path)
+        );
+        let mut table = db_open(&path, &TableConfig::new()).unwrap();
+        assert_eq!(table.num_rows, 20);
+        assert_eq!(table.read_row(19).unwrap().id(), 19);
+
         let mut input_buffer = InputBuffer::new();
-        let str = format!("insert -10 {} {}", long_username, long_email);
-        input_buffer.buffer_length = str.len() as i32;
-        input_buffer.buffer = Some(str);
-        let res = process_input(&mut input_buffer, &mut cursor);
-        assert!(matches!(res, Err(Error::PrepareNegativeId)));
+        input_buffer.buffer = Some(".recoveryfoo.db".to_string());
+        assert!(matches!(
+            do_meta_command(&input_buffer, &mut session),
+            MetaCommandResult::MetaCommandUnrecognizedCommand
+        ));
+        run(&mut session, ".recover").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "An in-memory database has no file to recover\n"
+        );
     }
+
     #[test]
-    fn testing_the_time_to_get_the_email() {
-        let table = Table::new();
+    fn rename_moves_the_file_and_keeps_the_rows() {
+        let dir = TestDir::new("rename");
+        let from_path = dir.path("rename-from.db");
+        let taken_path = dir.path("rename-taken.db");
+        let to_path = dir.path("rename-to.db");
+        write_fixture(&from_path, 5);
+        write_fixture(&taken_path, 1);
+        let mut session = Session::new(
+            db_open(&from_path, &TableConfig::new()).unwrap(),
+            Vec::new(),
+        );
+        run(&mut session, &format!(".rename {}", taken_path)).unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            format!("{:?} already exists\n", taken_path)
+        );
+
+        run(&mut session, &format!(".rename {}", to_path)).unwrap();
+        let old_exists = std::path::Path::new(&from_path).exists();
+        let new_exists = std::path::Path::new(&to_path).exists();
+        run(&mut session, "select").unwrap();
+        assert!(!old_exists);
+        assert!(new_exists);
+        assert_eq!(session.table.file_name.as_deref(), Some(to_path.as_str()));
+        assert_eq!(session.table.num_rows, 5);
+        assert!(take_output(&mut session).starts_with("Row 0 Row { id: 0, username: \"user0\""));
+    }
+
+    #[test]
+    fn verify_compares_num_rows_with_stored_rows() {
+        let dir = TestDir::new("verify");
+        let path = dir.path("verify.db");
+        write_fixture(&path, 20);
+        let mut session = Session::new(db_open(&path, &TableConfig::new()).unwrap(), Vec::new());
+        run(&mut session, "insert 20 bala bala@gmail.com").unwrap();
+        run(&mut session, "insert 21 bala bala@gmail.com").unwrap();
+        run(&mut session, ".verify").unwrap();
+        assert_eq!(take_output(&mut session), "ok: 22 rows\n");
+
+        session.table.num_rows = 25;
+        run(&mut session, ".verify").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "mismatch: num_rows is 25 but 22 rows are stored\n"
+        );
+        assert_eq!(session.table.num_rows, 25);
+    }
+
+    #[test]
+    fn named_insert_accepts_fields_in_any_order() {
+        let mut session = test_session();
+        run(&mut session, "insert email=a@x.com id=1 username=a").unwrap();
+        run(&mut session, "insert 2 b b@x.com").unwrap();
+        let mut row = Row::new();
+        deserialize_row(session.table.row_slot(0).unwrap(), &mut row, 0);
+        assert_eq!(
+            row,
+            Row::from_parts(1, "a".to_string(), "a@x.com".to_string()).unwrap()
+        );
+        assert_eq!(session.table.num_rows, 2);
+    }
+
+    #[test]
+    fn named_insert_requires_every_field_once() {
+        let mut session = test_session();
+        for command in [
+            "insert id=1 username=a",
+            "insert id=1 username=a email=a@x.com id=2",
+            "insert id=1 username=a email=a@x.com age=3",
+        ] {
+            assert!(matches!(
+                run(&mut session, command),
+                Err(Error::PrepareError)
+            ));
+        }
+        assert_eq!(session.table.num_rows, 0);
+    }
+
+    #[test]
+    fn truncation_never_splits_a_multibyte_char() {
+        // "é" takes two bytes, so "aé" ends on a boundary at byte 3.
+        assert_eq!(truncate_to_char_boundary("aéb", 3), "aé");
+        assert_eq!(truncate_to_char_boundary("aéb", 2), "a");
+        assert_eq!(truncate_to_char_boundary("abc", 10), "abc");
+    }
+
+    #[test]
+    fn select_where_username_like_filters_by_substring() {
+        let mut session = test_session();
+        run(&mut session, "insert 1 Balakrishna b@x.com").unwrap();
+        run(&mut session, "insert 2 anusha a@x.com").unwrap();
+        run(&mut session, "insert 3 shiva s@x.com").unwrap();
+        take_output(&mut session);
+        run(&mut session, ".mode list").unwrap();
+
+        run(&mut session, "select where username like krish").unwrap();
+        assert_eq!(take_output(&mut session), "1|Balakrishna|b@x.com\n");
+        run(&mut session, "select where username like SHA").unwrap();
+        assert_eq!(take_output(&mut session), "2|anusha|a@x.com\n");
+        run(&mut session, "select where username like zed").unwrap();
+        assert_eq!(take_output(&mut session), "(0 rows)\n");
+    }
+
+    #[test]
+    fn repeated_selects_hit_the_row_cache() {
+        let mut session = test_session();
+        insert_rows(&mut session, 2);
+        run(&mut session, "select rowid 1").unwrap();
+        let first = take_output(&mut session);
+        assert_eq!(session.table.row_cache.hits, 0);
+
+        run(&mut session, "select rowid 1").unwrap();
+        assert_eq!(session.table.row_cache.hits, 1);
+        assert_eq!(take_output(&mut session), first);
+
+        run(&mut session, "insert or replace 1 anu anu@gmail.com").unwrap();
+        let row = session.table.read_row(1).unwrap();
+        assert_eq!(row.username(), "anu");
+        assert_eq!(session.table.row_cache.hits, 1);
+    }
+
+    #[test]
+    fn get_page_rejects_pages_past_the_table() {
+        let mut table = memory_table();
+        assert!(matches!(
+            get_page(&mut table.pager, TABLE_MAX_PAGES),
+            Err(Error::PageOutOfBounds)
+        ));
+        assert!(get_page(&mut table.pager, TABLE_MAX_PAGES - 1).is_ok());
+    }
+
+    #[test]
+    fn pager_flush_returns_errors_instead_of_exiting() {
+        let mut pager = Pager::new(Box::new(std::io::Cursor::new(Vec::new())), 0);
+        let err = pager
+            .flush_run(TABLE_MAX_PAGES..TABLE_MAX_PAGES + 2)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        // A page that was never loaded has nothing to write.
+        let err = pager.flush_run(0..1).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(pager.metrics().pages_written, 0);
+    }
+
+    #[test]
+    fn select_where_email_matches_exactly() {
+        let mut session = test_session();
+        run(&mut session, "insert 1 bala bala@gmail.com").unwrap();
+        run(&mut session, "insert 2 anu 'anu at home'").unwrap();
+        run(&mut session, ".mode list").unwrap();
+
+        run(&mut session, "select where email = bala@gmail.com").unwrap();
+        assert_eq!(take_output(&mut session), "1|bala|bala@gmail.com\n");
+        run(&mut session, "select where email = bala@gmail").unwrap();
+        assert_eq!(take_output(&mut session), "(0 rows)\n");
+        run(&mut session, "select where email = \"anu at home\"").unwrap();
+        assert_eq!(take_output(&mut session), "2|anu|anu at home\n");
+    }
+
+    #[test]
+    fn select_where_id_prints_only_the_matching_row() {
+        let mut session = test_session();
+        insert_rows(&mut session, 3);
+        run(&mut session, ".mode list").unwrap();
+        take_output(&mut session);
+
+        run(&mut session, "select where id = 2").unwrap();
+        let filtered = take_output(&mut session);
+        assert_eq!(filtered.lines().count(), 1);
+        assert!(filtered.starts_with("2|"));
+        run(&mut session, "select where id = 42").unwrap();
+        assert_eq!(take_output(&mut session), "(0 rows)\n");
+        run(&mut session, "select").unwrap();
+        assert_eq!(take_output(&mut session).lines().count(), 3);
+
+        assert!(matches!(
+            run(&mut session, "select where id = two"),
+            Err(Error::PrepareError)
+        ));
+        assert!(matches!(
+            run(&mut session, "select where id = -1"),
+            Err(Error::PrepareNegativeId)
+        ));
+    }
+
+    #[test]
+    fn resident_pages_are_read_from_storage_once() {
+        let dir = TestDir::new("metrics");
+        let path = dir.path("metrics.db");
+        write_fixture(&path, 3);
+        let mut table = db_open(&path, &TableConfig::new()).unwrap();
+        get_page(&mut table.pager, 0).unwrap();
+        get_page(&mut table.pager, 0).unwrap();
+        assert_eq!(table.pager.metrics().pages_read, 1);
+
+        let row = Row::from_parts(3, "user3".to_string(), "user3@example.com".to_string()).unwrap();
+        table.bulk_insert(std::iter::once(row)).unwrap();
+        db_close(&mut table).unwrap();
+        assert_eq!(
+            table.pager.metrics(),
+            PagerMetrics {
+                pages_read: 1,
+                pages_written: 1
+            }
+        );
+    }
+
+    #[test]
+    fn durable_close_syncs_without_error() {
+        let dir = TestDir::new("durable");
+        let path = dir.path("durable.db");
+        let mut session = Session::new(db_open(&path, &TableConfig::new()).unwrap(), Vec::new());
+        run(&mut session, ".pragma synchronous").unwrap();
+        run(&mut session, ".pragma synchronous on").unwrap();
+        run(&mut session, ".pragma synchronous").unwrap();
+        assert_eq!(take_output(&mut session), "off\non\n");
+        insert_rows(&mut session, 3);
+        db_close(&mut session.table).unwrap();
+
+        let reopened = db_open(&path, &TableConfig::new()).unwrap();
+        assert_eq!(reopened.num_rows, 3);
+        assert!(!reopened.pager.durable);
+    }
+
+    #[test]
+    fn databases_prints_the_open_file() {
+        let dir = TestDir::new("databases");
+        let path = dir.path("databases.db");
+        let mut session = Session::new(db_open(&path, &TableConfig::new()).unwrap(), Vec::new());
+        run(&mut session, ".databases").unwrap();
+        let full_path = std::fs::canonicalize(&path).unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            format!("{}: {}\n", path, full_path.display())
+        );
+
+        let mut session = test_session();
+        run(&mut session, ".databases").unwrap();
+        assert_eq!(take_output(&mut session), "(in memory)\n");
+    }
+
+    #[test]
+    fn meta_commands_need_whitespace_before_their_arguments() {
+        let mut session = test_session();
         let mut input_buffer = InputBuffer::new();
-        let mut cursor = Cursor::new(table);
-        for i in 0..1399 {
-            let str = format!("insert {} bala {}@gmail.com", i, i as f64 * 1e9 + 7f64);
-            input_buffer.buffer_length = str.len() as i32;
-            input_buffer.buffer = Some(str);
-            let _ = process_input(&mut input_buffer, &mut cursor);
+        for command in [
+            ".readme",
+            ".pragmas",
+            ".seeds",
+            ".modes",
+            ".widths",
+            ".historyx",
+            ".timers",
+            ".echoes",
+        ] {
+            input_buffer.buffer = Some(command.to_string());
+            assert!(matches!(
+                do_meta_command(&input_buffer, &mut session),
+                MetaCommandResult::MetaCommandUnrecognizedCommand
+            ));
         }
-        let str = format!("select {}@gmail.com", 1388f64 * 1e9 + 7f64);
-        input_buffer.buffer_length = str.len() as i32;
-        input_buffer.buffer = Some(str);
-        let _ = process_input(&mut input_buffer, &mut cursor);
+        assert_eq!(take_output(&mut session), "");
+
+        run(&mut session, ".read").unwrap();
+        run(&mut session, ".mode").unwrap();
+        run(&mut session, ".history").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "Usage: .read <path> [--stop-on-error]\n\
+             Usage: .mode default|list|column|json|insert\n\
+             .read\n\
+             .mode\n"
+        );
+    }
+
+    #[test]
+    fn tables_lists_the_open_table_without_exiting() {
+        let mut session = test_session();
+        insert_rows(&mut session, 2);
+        take_output(&mut session);
+        let mut input_buffer = InputBuffer::new();
+        input_buffer.buffer = Some(".tables".to_string());
+        assert!(matches!(
+            do_meta_command(&input_buffer, &mut session),
+            MetaCommandResult::MetaCommandHandled
+        ));
+        assert_eq!(take_output(&mut session), "(in memory): 2 rows\n");
+
+        let dir = TestDir::new("tables");
+        let path = dir.path("tables.db");
+        let mut session = Session::new(db_open(&path, &TableConfig::new()).unwrap(), Vec::new());
+        run(&mut session, ".tables").unwrap();
+        assert_eq!(take_output(&mut session), format!("{}: 0 rows\n", path));
+    }
+
+    #[test]
+    fn constants_prints_the_layout_sizes() {
+        let mut session = test_session();
+        let mut input_buffer = InputBuffer::new();
+        input_buffer.buffer = Some(".constants".to_string());
+        assert!(matches!(
+            do_meta_command(&input_buffer, &mut session),
+            MetaCommandResult::MetaCommandHandled
+        ));
+        assert_eq!(
+            take_output(&mut session),
+            format!(
+                "ROW_SIZE: 291\nID_SIZE: 4\nUSERNAME_SIZE: 32\nEMAIL_SIZE: 255\n\
+                 PAGE_SIZE: 4096\nROWS_PER_PAGE: {}\nTABLE_MAX_ROWS: {}\n",
+                ROWS_PER_PAGE, TABLE_MAX_ROWS
+            )
+        );
+    }
+
+    #[test]
+    fn inserts_between_selects_append_at_the_end() {
+        let mut session = test_session();
+        run(&mut session, "insert 1 a a@x.com").unwrap();
+        run(&mut session, "select").unwrap();
+        run(&mut session, "insert 2 b b@x.com").unwrap();
+        run(&mut session, "select rowid 0").unwrap();
+        run(&mut session, "insert 3 c c@x.com").unwrap();
+        run(&mut session, "select a@x.com").unwrap();
+        run(&mut session, "insert 4 d d@x.com").unwrap();
+        take_output(&mut session);
+
+        run(&mut session, ".mode list").unwrap();
+        run(&mut session, "select").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "1|a|a@x.com\n2|b|b@x.com\n3|c|c@x.com\n4|d|d@x.com\n"
+        );
+    }
+
+    #[test]
+    fn append_only_tables_accept_inserts_but_not_clear() {
+        let mut session = Session::new(memory_table().with_append_only(true), Vec::new());
+        session.force = true;
+        insert_rows(&mut session, 2);
+        run(&mut session, ".clear").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "AppendOnly: the table only accepts inserts\n"
+        );
+        assert!(matches!(session.table.clear(), Err(Error::AppendOnly)));
+        assert_eq!(session.table.num_rows, 2);
+    }
+
+    #[test]
+    fn append_only_tables_reject_delete() {
+        let mut session = Session::new(memory_table().with_append_only(true), Vec::new());
+        insert_rows(&mut session, 2);
+        assert!(matches!(
+            run(&mut session, "delete 1"),
+            Err(Error::AppendOnly)
+        ));
+        assert_eq!(
+            take_output(&mut session),
+            "AppendOnly: the table only accepts inserts\n"
+        );
+        assert_eq!(session.table.num_rows, 2);
+    }
+
+    #[test]
+    fn append_only_tables_reject_update() {
+        let mut session = Session::new(memory_table().with_append_only(true), Vec::new());
+        insert_rows(&mut session, 2);
+        assert!(matches!(
+            run(&mut session, "update 1 set username=anu"),
+            Err(Error::AppendOnly)
+        ));
+        assert_eq!(
+            take_output(&mut session),
+            "AppendOnly: the table only accepts inserts\n"
+        );
+        assert_eq!(session.table.read_row(1).unwrap().username(), "bala");
+    }
+
+    #[test]
+    fn append_only_tables_reject_insert_or_replace() {
+        let mut session = Session::new(memory_table().with_append_only(true), Vec::new());
+        insert_rows(&mut session, 2);
+        assert!(matches!(
+            run(&mut session, "insert or replace 1 anu anu@gmail.com"),
+            Err(Error::AppendOnly)
+        ));
+        assert_eq!(
+            take_output(&mut session),
+            "AppendOnly: the table only accepts inserts\n"
+        );
+        run(&mut session, "insert or replace 2 anu anu@gmail.com").unwrap();
+        assert_eq!(session.table.num_rows, 3);
+    }
+
+    #[test]
+    fn insert_or_replace_updates_an_existing_id() {
+        let mut session = test_session();
+        insert_rows(&mut session, 2);
+        run(&mut session, ".mode list").unwrap();
+
+        run(&mut session, "insert or replace 1 anu anu@gmail.com").unwrap();
+        assert_eq!(session.table.num_rows, 2);
+        run(&mut session, "select rowid 1").unwrap();
+        assert_eq!(take_output(&mut session), "1|anu|anu@gmail.com\n");
+    }
+
+    #[test]
+    fn insert_or_replace_inserts_a_new_id() {
+        let mut session = test_session();
+        insert_rows(&mut session, 2);
+        run(&mut session, ".mode list").unwrap();
+
+        run(
+            &mut session,
+            "insert or replace id=7 username=anu email=anu@gmail.com",
+        )
+        .unwrap();
+        assert_eq!(session.table.num_rows, 3);
+        run(&mut session, "select tail 1").unwrap();
+        assert_eq!(take_output(&mut session), "7|anu|anu@gmail.com\n");
+    }
+
+    #[test]
+    fn update_of_the_email_keeps_the_username() {
+        let mut session = test_session();
+        insert_rows(&mut session, 2);
+        run(&mut session, ".mode list").unwrap();
+
+        run(&mut session, "update 1 set email=anu@gmail.com").unwrap();
+        assert_eq!(session.changes, 1);
+        run(&mut session, "select rowid 1").unwrap();
+        assert_eq!(take_output(&mut session), "1|bala|anu@gmail.com\n");
+    }
+
+    #[test]
+    fn update_of_the_username_keeps_the_email() {
+        let mut session = test_session();
+        insert_rows(&mut session, 2);
+        run(&mut session, ".mode list").unwrap();
+
+        run(&mut session, "update 0 set username=anu").unwrap();
+        assert!(matches!(
+            run(&mut session, "update 9 set username=anu, email=x@y.com"),
+            Err(Error::NotFound)
+        ));
+        take_output(&mut session);
+        run(&mut session, "select").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "0|anu|bala@gmail.com\n1|bala|bala@gmail.com\n"
+        );
+        assert!(run(&mut session, "update 0 set id=3").is_err());
+    }
+
+    #[test]
+    fn positional_update_rewrites_both_columns() {
+        let mut session = test_session();
+        insert_rows(&mut session, 2);
+        run(&mut session, ".mode list").unwrap();
+
+        run(&mut session, "update 1 anu anu@gmail.com").unwrap();
+        assert_eq!(session.changes, 1);
+        run(&mut session, "select").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "0|bala|bala@gmail.com\n1|anu|anu@gmail.com\n"
+        );
+    }
+
+    #[test]
+    fn positional_update_of_a_missing_id_is_reported() {
+        let mut session = test_session();
+        insert_rows(&mut session, 1);
+
+        assert!(matches!(
+            run(&mut session, "update 4 anu anu@gmail.com"),
+            Err(Error::NotFound)
+        ));
+        let long_name = "a".repeat(USERNAME_MAX_LENGTH + 1);
+        assert!(matches!(
+            run(
+                &mut session,
+                &format!("update 0 {} anu@gmail.com", long_name)
+            ),
+            Err(Error::PrepareStringTooLong)
+        ));
+        assert!(run(&mut session, "update 0 anu").is_err());
+    }
+
+    #[test]
+    fn delete_removes_the_row_and_closes_the_gap() {
+        let mut session = test_session();
+        insert_rows(&mut session, 3);
+        run(&mut session, ".mode list").unwrap();
+
+        run(&mut session, "delete 1").unwrap();
+        assert_eq!(session.changes, 1);
+        assert_eq!(session.table.num_rows, 2);
+        run(&mut session, "select").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "0|bala|bala@gmail.com\n2|bala|bala@gmail.com\n"
+        );
+    }
+
+    #[test]
+    fn delete_of_a_missing_id_is_reported() {
+        let mut session = test_session();
+        insert_rows(&mut session, 2);
+
+        assert!(matches!(
+            run(&mut session, "delete 7"),
+            Err(Error::NotFound)
+        ));
+        assert_eq!(take_output(&mut session), "Error: No row has that id.\n");
+        assert_eq!(session.table.num_rows, 2);
+    }
+
+    #[test]
+    fn empty_usernames_are_rejected() {
+        let mut session = test_session();
+        assert!(matches!(
+            run(&mut session, "insert 1 '' bala@gmail.com"),
+            Err(Error::PrepareEmptyValue)
+        ));
+        assert!(matches!(
+            run(&mut session, "insert id=1 username= email=bala@gmail.com"),
+            Err(Error::PrepareEmptyValue)
+        ));
+        assert_eq!(
+            take_output(&mut session),
+            "Username and email can't be empty\n".repeat(2)
+        );
+        assert_eq!(session.table.num_rows, 0);
+    }
+
+    #[test]
+    fn empty_emails_are_rejected() {
+        let mut session = test_session();
+        assert!(matches!(
+            run(&mut session, "insert 1 bala \"\""),
+            Err(Error::PrepareEmptyValue)
+        ));
+        assert!(matches!(
+            Row::from_parts(1, "bala", ""),
+            Err(PrepareResult::PrepareEmptyValue)
+        ));
+        assert_eq!(session.table.num_rows, 0);
+    }
+
+    #[test]
+    fn syntax_errors_report_where_the_insert_went_wrong() {
+        let mut session = test_session();
+        run(&mut session, "insert 1 bala").unwrap_err();
+        assert_eq!(take_output(&mut session), "Syntax error near position 13\n");
+        run(&mut session, "insert one bala bala@gmail.com").unwrap_err();
+        assert_eq!(take_output(&mut session), "Syntax error near position 7\n");
+    }
+
+    #[test]
+    fn insert_mode_prints_statements_that_parse_back() {
+        let mut session = test_session();
+        run(&mut session, "insert 3 bala bala@gmail.com").unwrap();
+        run(&mut session, ".mode insert").unwrap();
+        run(&mut session, "select").unwrap();
+        let output = take_output(&mut session);
+        assert_eq!(output, "insert 3 bala bala@gmail.com\n");
+
+        let mut input_buffer = InputBuffer::new();
+        input_buffer.buffer = Some(output.trim_end().to_owned());
+        let mut statement = Statement::new();
+        assert!(matches!(
+            prepare_statement(&input_buffer, &mut statement),
+            PrepareResult::PrepareSuccess
+        ));
+        assert_eq!(
+            statement.row_to_insert,
+            Row::from_parts(3, "bala", "bala@gmail.com").unwrap()
+        );
+
+        let mut session = test_session();
+        run(&mut session, "insert 2 anu 'anu at home'").unwrap();
+        run(&mut session, "insert 4 o'neil \"say 'hi'--now\"").unwrap();
+        run(&mut session, ".mode insert").unwrap();
+        run(&mut session, "select").unwrap();
+        let output = take_output(&mut session);
+        assert_eq!(
+            output,
+            "insert 2 anu 'anu at home'\ninsert 4 'o''neil' 'say ''hi''--now'\n"
+        );
+        let expected = [
+            Row::from_parts(2, "anu", "anu at home").unwrap(),
+            Row::from_parts(4, "o'neil", "say 'hi'--now").unwrap(),
+        ];
+        for (line, expected) in output.lines().zip(expected) {
+            let mut replay = test_session();
+            run(&mut replay, line).unwrap();
+            assert_eq!(replay.table.read_row(0).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn ids_above_i32_max_are_stored() {
+        let mut session = test_session();
+        run(&mut session, "insert 4294967295 bala bala@gmail.com").unwrap();
+        let mut row = Row::new();
+        deserialize_row(session.table.row_slot(0).unwrap(), &mut row, 0);
+        assert_eq!(row.id(), u32::MAX);
+        assert!(matches!(
+            run(&mut session, "insert 4294967296 bala bala@gmail.com"),
+            Err(Error::PrepareError)
+        ));
+        assert!(matches!(
+            run(
+                &mut session,
+                "insert id=-1 username=bala email=bala@gmail.com"
+            ),
+            Err(Error::PrepareNegativeId)
+        ));
+    }
+
+    #[test]
+    fn from_bytes_loads_a_db_image() {
+        // Padded with 0xff, which the header records, while this session pads with 0.
+        let mut image = encode_header(Schema::Users, 1, 0xff).to_vec();
+        let mut page = [0; PAGE_SIZE];
+        let layout = Schema::Users.layout();
+        let row = Row::from_parts(7, "bala", "bala@gmail.com").unwrap();
+        initialize_leaf_node(&mut page, true);
+        set_leaf_node_num_cells(&mut page, 1);
+        set_leaf_node_key(&mut page, layout, 0, 7);
+        serialize_row(&row, leaf_node_value_mut(&mut page, layout, 0), 0xff);
+        stamp_checksum(&mut page);
+        image.extend_from_slice(&page);
+
+        let mut table = Table::from_bytes(&image).unwrap();
+        assert_eq!(table.num_rows, 1);
+        assert_eq!(table.read_row(0).unwrap(), row);
+        assert_eq!(table.to_bytes().unwrap(), image);
+        // Only the cells the leaf header counts can be read.
+        assert!(table.row_slot(1).is_err());
+        assert!(matches!(
+            Table::from_bytes(&image[..100]),
+            Err(Error::DbCorruptFile)
+        ));
+    }
+
+    #[test]
+    fn to_bytes_matches_the_on_disk_layout() {
+        let mut session = test_session();
+        run(&mut session, "insert 1 bala bala@gmail.com").unwrap();
+
+        let mut expected = vec![0; HEADER_SIZE + PAGE_SIZE];
+        expected[..12].copy_from_slice(b"try-db\0\0\x08\0\0\0");
+        // Users schema, then the row count and a zero fill byte.
+        expected[16..20].copy_from_slice(&1u32.to_le_bytes());
+        let page = &mut expected[HEADER_SIZE..];
+        // A root leaf holding one cell, keyed by the row's id.
+        page[..10].copy_from_slice(&[1, 1, 0, 0, 0, 0, 1, 0, 0, 0]);
+        page[10..14].copy_from_slice(&1u32.to_le_bytes());
+        let row = &mut page[14..];
+        row[..4].copy_from_slice(&1u32.to_ne_bytes());
+        row[4..8].copy_from_slice(b"bala");
+        row[36..50].copy_from_slice(b"bala@gmail.com");
+        let checksum = crc32(&expected[HEADER_SIZE..HEADER_SIZE + PAGE_SIZE - 4]);
+        expected[HEADER_SIZE + PAGE_SIZE - 4..].copy_from_slice(&checksum.to_le_bytes());
+        assert_eq!(session.table.to_bytes().unwrap(), expected);
+    }
+
+    #[test]
+    fn to_bytes_round_trips_through_from_bytes() {
+        let mut session = test_session();
+        insert_rows(&mut session, ROWS_PER_PAGE + 3);
+        let image = session.table.to_bytes().unwrap();
+
+        let mut copy = Table::from_bytes(&image).unwrap();
+        assert_eq!(copy.num_rows, ROWS_PER_PAGE + 3);
+        for row_num in 0..copy.num_rows {
+            let (mut original, mut loaded) = (Row::new(), Row::new());
+            deserialize_row(session.table.row_slot(row_num).unwrap(), &mut original, 0);
+            deserialize_row(copy.row_slot(row_num).unwrap(), &mut loaded, 0);
+            assert_eq!(loaded, original);
+        }
+    }
+
+    #[test]
+    fn row_from_parts_validates_in_one_place() {
+        let row = Row::from_parts(1, "bala", "bala@gmail.com").unwrap();
+        assert_eq!(row.username(), "bala");
+        assert!(matches!(
+            Row::from_parts(1, "a".repeat(USERNAME_MAX_LENGTH + 1), "bala@gmail.com"),
+            Err(PrepareResult::PrepareStringTooLong)
+        ));
+    }
+
+    #[test]
+    fn select_len_prints_the_length_of_each_username() {
+        let mut session = test_session();
+        run(&mut session, "insert 1 bala bala@gmail.com").unwrap();
+        run(&mut session, "insert 2 'é anu' anu@gmail.com").unwrap();
+        run(&mut session, ".mode json").unwrap();
+
+        run(&mut session, "select len(username)").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "{\"len(username)\": 4}\n{\"len(username)\": 5}\n"
+        );
+        assert!(run(&mut session, "select len(id)").is_err());
+    }
+
+    #[test]
+    fn select_distinct_username_prints_each_name_once() {
+        let mut session = test_session();
+        for (id, username) in ["bala", "anu", "bala", "zed", "anu"].iter().enumerate() {
+            run(&mut session, &format!("insert {} {} x@y.com", id, username)).unwrap();
+        }
+        run(&mut session, ".mode list").unwrap();
+        run(&mut session, "select distinct username").unwrap();
+        assert_eq!(take_output(&mut session), "bala\nanu\nzed\n");
+    }
+
+    #[test]
+    fn select_count_groups_rows_by_username() {
+        let mut session = test_session();
+        for (id, username) in ["zed", "bala", "anu", "bala", "zed", "bala"]
+            .iter()
+            .enumerate()
+        {
+            run(&mut session, &format!("insert {} {} x@y.com", id, username)).unwrap();
+        }
+        run(&mut session, ".mode list").unwrap();
+        run(&mut session, "select count(*) group by username").unwrap();
+        assert_eq!(take_output(&mut session), "anu|1\nbala|3\nzed|2\n");
+
+        assert!(run(&mut session, "select count(*)").is_err());
+        assert_eq!(
+            take_output(&mut session),
+            "Syntax error: could not parse statement\n"
+        );
+    }
+
+    #[test]
+    fn min_max_and_avg_fold_the_ids() {
+        let mut session = test_session();
+        run(&mut session, ".mode list").unwrap();
+        for id in [4, 9, 2] {
+            run(&mut session, &format!("insert {} bala bala@gmail.com", id)).unwrap();
+        }
+        for (query, expected) in [("min(id)", "2"), ("max(id)", "9"), ("avg(id)", "5.00")] {
+            run(&mut session, &format!("select {}", query)).unwrap();
+            assert_eq!(take_output(&mut session), format!("{}\n", expected));
+        }
+    }
+
+    #[test]
+    fn aggregates_over_an_empty_table_say_there_are_no_rows() {
+        let mut session = test_session();
+        for query in ["min(id)", "max(id)", "avg(id)"] {
+            run(&mut session, &format!("select {}", query)).unwrap();
+            assert_eq!(take_output(&mut session), format!("{}: no rows\n", query));
+        }
+    }
+
+    #[test]
+    fn select_into_copies_the_matching_rows_to_a_new_db() {
+        let dir = TestDir::new("select-into");
+        let path = dir.path("select_into");
+        let mut session = test_session();
+        for (id, username) in ["bala", "anu", "balaji"].iter().enumerate() {
+            run(&mut session, &format!("insert {} {} x@y.com", id, username)).unwrap();
+        }
+        let into = format!("select into {}", path);
+        run(&mut session, &format!("{} where username like bal", into)).unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            format!("Copied 2 rows into {:?}\n", path)
+        );
+        assert!(run(&mut session, &into).is_err());
+
+        let mut copy = db_open(&path, &TableConfig::new()).unwrap();
+        let names: Vec<String> = (0..copy.num_rows)
+            .map(|row_num| copy.read_row(row_num).unwrap().username().to_owned())
+            .collect();
+        assert_eq!(names, ["bala", "balaji"]);
+    }
+
+    #[test]
+    fn raw_rows_keep_bytes_that_are_not_utf8() {
+        let mut session = test_session();
+        run(&mut session, "insert raw 7 62e46c61 746F6BFFFE").unwrap();
+        let raw = RawRow::from_parts(7, b"b\xe4la".to_vec(), b"tok\xff\xfe".to_vec(), 0).unwrap();
+        assert_eq!(session.table.read_raw_row(0).unwrap(), raw);
+
+        run(&mut session, ".mode list").unwrap();
+        run(&mut session, "select raw").unwrap();
+        assert_eq!(take_output(&mut session), "7|62e46c61|746f6bfffe\n");
+    }
+
+    #[test]
+    fn insert_raw_takes_hex_that_fits_the_table() {
+        let mut session = test_session();
+        for bad in ["6g6c", "626", "+f62", "6200"] {
+            let res = run(&mut session, &format!("insert raw 1 {} 62", bad));
+            assert!(matches!(res, Err(Error::PrepareError)), "{}", bad);
+        }
+        let res = run(&mut session, "insert raw 1 62");
+        assert!(matches!(res, Err(Error::PrepareError)));
+        assert_eq!(session.table.num_rows, 0);
+
+        // A value holding the table's fill byte would be cut short where it's stored.
+        let table = Table::from_bytes(&encode_header(Schema::Users, 0, 0xff)[..]).unwrap();
+        let mut session = Session::new(table, Vec::new());
+        let res = run(&mut session, "insert raw 1 62ff 62");
+        assert!(matches!(res, Err(Error::PrepareError)));
+        run(&mut session, "insert raw 1 62fe 62").unwrap();
+        assert_eq!(session.table.num_rows, 1);
+    }
+
+    #[test]
+    fn inserts_between_selects_land_after_the_last_row() {
+        let mut session = test_session();
+        run(&mut session, ".mode list").unwrap();
+        run(&mut session, "insert 1 bala bala@gmail.com").unwrap();
+        run(&mut session, "select").unwrap();
+        assert_eq!(take_output(&mut session).lines().count(), 1);
+
+        run(&mut session, "insert 2 anu anu@gmail.com").unwrap();
+        assert_eq!(session.table.num_rows, 2);
+        run(&mut session, "select").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "1|bala|bala@gmail.com\n2|anu|anu@gmail.com\n"
+        );
+    }
+
+    #[test]
+    fn select_tail_prints_the_rows_with_the_highest_ids() {
+        let mut session = test_session();
+        insert_rows(&mut session, 5);
+        run(&mut session, ".mode list").unwrap();
+
+        run(&mut session, "select tail 2").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "3|bala|bala@gmail.com\n4|bala|bala@gmail.com\n"
+        );
+        run(&mut session, "select tail 9").unwrap();
+        assert_eq!(take_output(&mut session).lines().count(), 5);
+        run(&mut session, "select tail 0").unwrap();
+        assert_eq!(take_output(&mut session), "(0 rows)\n");
+    }
+
+    #[test]
+    fn select_tail_follows_id_order_not_insertion_order() {
+        let mut session = test_session();
+        run(&mut session, ".mode list").unwrap();
+        for id in [9, 2, 6, 1] {
+            run(
+                &mut session,
+                &format!("insert {} user{} u{}@x.com", id, id, id),
+            )
+            .unwrap();
+        }
+
+        run(&mut session, "select tail 2").unwrap();
+        assert_eq!(
+            take_output(&mut session),
+            "6|user6|u6@x.com\n9|user9|u9@x.com\n"
+        );
     }
 }
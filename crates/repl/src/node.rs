@@ -0,0 +1,301 @@
+//! The node header every data page starts with, and the cells of leaf and internal
+//! nodes. A leaf holds its cells in order behind the header, each one a key followed by
+//! the row it belongs to; `num_cells` in the header says how many of them are in use.
+//! An internal node holds child page numbers in key order, each paired with the largest
+//! key under it and the number of rows under it, and a right child for the keys past
+//! the last one, whose row count sits in the header.
+
+use std::ops::Range;
+
+use crate::{
+    Layout, INTERNAL_NODE_CELL_SIZE, INTERNAL_NODE_CHILD_SIZE, INTERNAL_NODE_HEADER_SIZE,
+    INTERNAL_NODE_KEY_SIZE, INTERNAL_NODE_NUM_KEYS_OFFSET, INTERNAL_NODE_RIGHT_CHILD_OFFSET,
+    INTERNAL_NODE_RIGHT_CHILD_ROWS_OFFSET, INTERNAL_NODE_ROWS_SIZE, IS_ROOT_OFFSET,
+    LEAF_NODE_HEADER_SIZE, LEAF_NODE_KEY_SIZE, LEAF_NODE_NUM_CELLS_OFFSET, NODE_TYPE_OFFSET,
+    PARENT_POINTER_OFFSET,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum NodeType {
+    NodeInternal,
+    NodeLeaf,
+}
+
+impl NodeType {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(NodeType::NodeInternal),
+            1 => Some(NodeType::NodeLeaf),
+            _ => None,
+        }
+    }
+}
+
+/// The type recorded in the page's header, or `None` for a byte no node type uses.
+pub(crate) fn node_type(page: &[u8]) -> Option<NodeType> {
+    NodeType::from_byte(page[NODE_TYPE_OFFSET])
+}
+
+pub(crate) fn set_node_type(page: &mut [u8], node_type: NodeType) {
+    page[NODE_TYPE_OFFSET] = node_type as u8;
+}
+
+pub(crate) fn is_node_root(page: &[u8]) -> bool {
+    page[IS_ROOT_OFFSET] != 0
+}
+
+pub(crate) fn set_node_root(page: &mut [u8], is_root: bool) {
+    page[IS_ROOT_OFFSET] = u8::from(is_root);
+}
+
+/// Page number of the internal node this one is a child of; unused on the root.
+pub(crate) fn node_parent(page: &[u8]) -> usize {
+    read_u32(page, PARENT_POINTER_OFFSET) as usize
+}
+
+pub(crate) fn set_node_parent(page: &mut [u8], parent: usize) {
+    write_u32(page, PARENT_POINTER_OFFSET, parent as u32);
+}
+
+pub(crate) fn leaf_node_num_cells(page: &[u8]) -> usize {
+    read_u32(page, LEAF_NODE_NUM_CELLS_OFFSET) as usize
+}
+
+pub(crate) fn set_leaf_node_num_cells(page: &mut [u8], num_cells: usize) {
+    write_u32(page, LEAF_NODE_NUM_CELLS_OFFSET, num_cells as u32);
+}
+
+pub(crate) fn leaf_node_key(page: &[u8], layout: Layout, cell_num: usize) -> u32 {
+    read_u32(page, layout.cell_offset(cell_num))
+}
+
+pub(crate) fn set_leaf_node_key(page: &mut [u8], layout: Layout, cell_num: usize, key: u32) {
+    write_u32(page, layout.cell_offset(cell_num), key);
+}
+
+/// The row stored in a cell, right after its key.
+pub(crate) fn leaf_node_value(page: &[u8], layout: Layout, cell_num: usize) -> &[u8] {
+    let start = layout.value_offset(cell_num);
+    &page[start..start + layout.row_size]
+}
+
+pub(crate) fn leaf_node_value_mut(page: &mut [u8], layout: Layout, cell_num: usize) -> &mut [u8] {
+    let start = layout.value_offset(cell_num);
+    &mut page[start..start + layout.row_size]
+}
+
+/// The first cell whose key isn't smaller than `key`, found by binary search, or
+/// `num_cells` when every key is smaller.
+pub(crate) fn leaf_node_find(page: &[u8], layout: Layout, key: u32) -> usize {
+    let (mut low, mut high) = (0, leaf_node_num_cells(page));
+    while low < high {
+        let mid = (low + high) / 2;
+        if leaf_node_key(page, layout, mid) < key {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
+/// Opens a cell for `key` at `cell_num`, moving the cells from there on up by one, and
+/// returns its row to fill in. The leaf must have room for another cell.
+pub(crate) fn leaf_node_insert(
+    page: &mut [u8],
+    layout: Layout,
+    cell_num: usize,
+    key: u32,
+) -> &mut [u8] {
+    let num_cells = leaf_node_num_cells(page);
+    debug_assert!(cell_num <= num_cells && num_cells < layout.rows_per_page);
+    page.copy_within(
+        layout.cell_offset(cell_num)..layout.cell_offset(num_cells),
+        layout.cell_offset(cell_num + 1),
+    );
+    set_leaf_node_num_cells(page, num_cells + 1);
+    set_leaf_node_key(page, layout, cell_num, key);
+    leaf_node_value_mut(page, layout, cell_num)
+}
+
+/// Removes cell `cell_num`, moving the cells after it down by one and zeroing the cell
+/// that frees up at the end.
+pub(crate) fn leaf_node_delete(page: &mut [u8], layout: Layout, cell_num: usize) {
+    let num_cells = leaf_node_num_cells(page);
+    debug_assert!(cell_num < num_cells);
+    page.copy_within(
+        layout.cell_offset(cell_num + 1)..layout.cell_offset(num_cells),
+        layout.cell_offset(cell_num),
+    );
+    page[leaf_node_cell_range(layout, num_cells - 1)].fill(0);
+    set_leaf_node_num_cells(page, num_cells - 1);
+}
+
+/// Turns a page into an empty leaf. The parent pointer starts at 0, the root's page.
+pub(crate) fn initialize_leaf_node(page: &mut [u8], is_root: bool) {
+    page[..LEAF_NODE_HEADER_SIZE].fill(0);
+    set_node_type(page, NodeType::NodeLeaf);
+    set_node_root(page, is_root);
+}
+
+pub(crate) fn internal_node_num_keys(page: &[u8]) -> usize {
+    read_u32(page, INTERNAL_NODE_NUM_KEYS_OFFSET) as usize
+}
+
+pub(crate) fn set_internal_node_num_keys(page: &mut [u8], num_keys: usize) {
+    write_u32(page, INTERNAL_NODE_NUM_KEYS_OFFSET, num_keys as u32);
+}
+
+pub(crate) fn internal_node_right_child(page: &[u8]) -> usize {
+    read_u32(page, INTERNAL_NODE_RIGHT_CHILD_OFFSET) as usize
+}
+
+pub(crate) fn set_internal_node_right_child(page: &mut [u8], child: usize) {
+    write_u32(page, INTERNAL_NODE_RIGHT_CHILD_OFFSET, child as u32);
+}
+
+fn internal_node_cell_offset(cell_num: usize) -> usize {
+    INTERNAL_NODE_HEADER_SIZE + cell_num * INTERNAL_NODE_CELL_SIZE
+}
+
+/// Page number of child `child_num`, counting the right child as the one past the
+/// last key.
+pub(crate) fn internal_node_child(page: &[u8], child_num: usize) -> usize {
+    if child_num == internal_node_num_keys(page) {
+        internal_node_right_child(page)
+    } else {
+        read_u32(page, internal_node_cell_offset(child_num)) as usize
+    }
+}
+
+pub(crate) fn set_internal_node_child(page: &mut [u8], child_num: usize, child: usize) {
+    if child_num == internal_node_num_keys(page) {
+        set_internal_node_right_child(page, child);
+    } else {
+        write_u32(page, internal_node_cell_offset(child_num), child as u32);
+    }
+}
+
+/// The largest key under child `key_num`.
+pub(crate) fn internal_node_key(page: &[u8], key_num: usize) -> u32 {
+    read_u32(
+        page,
+        internal_node_cell_offset(key_num) + INTERNAL_NODE_CHILD_SIZE,
+    )
+}
+
+pub(crate) fn set_internal_node_key(page: &mut [u8], key_num: usize, key: u32) {
+    write_u32(
+        page,
+        internal_node_cell_offset(key_num) + INTERNAL_NODE_CHILD_SIZE,
+        key,
+    );
+}
+
+/// Where the row count of child `child_num` is stored, counting the right child as the
+/// one past the last key.
+pub(crate) fn internal_node_rows_range(page: &[u8], child_num: usize) -> Range<usize> {
+    let start = if child_num == internal_node_num_keys(page) {
+        INTERNAL_NODE_RIGHT_CHILD_ROWS_OFFSET
+    } else {
+        internal_node_cell_offset(child_num) + INTERNAL_NODE_CHILD_SIZE + INTERNAL_NODE_KEY_SIZE
+    };
+    start..start + INTERNAL_NODE_ROWS_SIZE
+}
+
+/// How many rows the subtree under child `child_num` holds.
+pub(crate) fn internal_node_child_rows(page: &[u8], child_num: usize) -> usize {
+    read_u32(page, internal_node_rows_range(page, child_num).start) as usize
+}
+
+pub(crate) fn set_internal_node_child_rows(page: &mut [u8], child_num: usize, rows: usize) {
+    let offset = internal_node_rows_range(page, child_num).start;
+    write_u32(page, offset, rows as u32);
+}
+
+/// The child `key` belongs under: the first one whose key isn't smaller, or the right
+/// child when every key is smaller.
+pub(crate) fn internal_node_find_child(page: &[u8], key: u32) -> usize {
+    let (mut low, mut high) = (0, internal_node_num_keys(page));
+    while low < high {
+        let mid = (low + high) / 2;
+        if internal_node_key(page, mid) < key {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
+/// Adds `child` under `key`, holding `rows` rows, as child `cell_num`, moving the cells
+/// from there on up by one. The right child stays where it is.
+pub(crate) fn internal_node_insert(
+    page: &mut [u8],
+    cell_num: usize,
+    child: usize,
+    key: u32,
+    rows: usize,
+) {
+    let num_keys = internal_node_num_keys(page);
+    page.copy_within(
+        internal_node_cell_offset(cell_num)..internal_node_cell_offset(num_keys),
+        internal_node_cell_offset(cell_num + 1),
+    );
+    set_internal_node_num_keys(page, num_keys + 1);
+    write_u32(page, internal_node_cell_offset(cell_num), child as u32);
+    set_internal_node_key(page, cell_num, key);
+    set_internal_node_child_rows(page, cell_num, rows);
+}
+
+/// Turns a page into an internal node with no keys.
+pub(crate) fn initialize_internal_node(page: &mut [u8], is_root: bool) {
+    page[..INTERNAL_NODE_HEADER_SIZE].fill(0);
+    set_node_type(page, NodeType::NodeInternal);
+    set_node_root(page, is_root);
+}
+
+/// Bytes of a whole cell, its key and its row.
+pub(crate) fn leaf_node_cell_range(layout: Layout, cell_num: usize) -> Range<usize> {
+    let start = layout.cell_offset(cell_num);
+    start..start + LEAF_NODE_KEY_SIZE + layout.row_size
+}
+
+fn read_u32(page: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(page[offset..offset + size_of::<u32>()].try_into().unwrap())
+}
+
+fn write_u32(page: &mut [u8], offset: usize, value: u32) {
+    page[offset..offset + size_of::<u32>()].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        initialize_leaf_node, is_node_root, leaf_node_key, leaf_node_num_cells, leaf_node_value,
+        leaf_node_value_mut, node_type, set_leaf_node_key, set_leaf_node_num_cells, NodeType,
+    };
+    use crate::{Schema, PAGE_SIZE, ROW_SIZE};
+
+    #[test]
+    fn cells_are_read_through_the_leaf_header() {
+        let layout = Schema::Users.layout();
+        let mut page = [0xaa; PAGE_SIZE];
+        initialize_leaf_node(&mut page, true);
+        assert_eq!(node_type(&page), Some(NodeType::NodeLeaf));
+        assert!(is_node_root(&page));
+        assert_eq!(leaf_node_num_cells(&page), 0);
+
+        for (cell_num, key) in [(0, 3), (1, 9)] {
+            set_leaf_node_key(&mut page, layout, cell_num, key);
+            leaf_node_value_mut(&mut page, layout, cell_num).fill(key as u8);
+        }
+        set_leaf_node_num_cells(&mut page, 2);
+        assert_eq!(leaf_node_num_cells(&page), 2);
+        assert_eq!(leaf_node_key(&page, layout, 1), 9);
+        assert_eq!(leaf_node_value(&page, layout, 1), [9; ROW_SIZE]);
+        // The second cell starts right where the first one's row ends.
+        assert_eq!(leaf_node_value(&page, layout, 0), [3; ROW_SIZE]);
+        assert_eq!(layout.cell_offset(1), layout.value_offset(0) + ROW_SIZE);
+    }
+}
@@ -0,0 +1,281 @@
+//! The users row, as text or as raw bytes, and its fixed-size on-disk encoding. Fields
+//! are only reachable through accessors, so callers don't depend on how a row is laid
+//! out.
+
+use std::ptr;
+
+use crate::{
+    truncate_to_char_boundary, validate_row, Field, PrepareResult, EMAIL_MAX_LENGTH, EMAIL_OFFSET,
+    EMAIL_SIZE, ID_OFFSET, ID_SIZE, USERNAME_MAX_LENGTH, USERNAME_OFFSET, USERNAME_SIZE,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Row {
+    id: u32,
+    username: String,
+    email: String,
+}
+
+impl Row {
+    pub(crate) fn new() -> Self {
+        Row {
+            id: 0,
+            username: String::with_capacity(USERNAME_SIZE),
+            email: String::with_capacity(EMAIL_SIZE),
+        }
+    }
+    /// Builds a row that is known to fit the users layout, or says why it doesn't.
+    pub(crate) fn from_parts(
+        id: u32,
+        username: impl Into<String>,
+        email: impl Into<String>,
+    ) -> Result<Row, PrepareResult> {
+        let row = Row {
+            id,
+            username: username.into(),
+            email: email.into(),
+        };
+        match validate_row(&row) {
+            PrepareResult::PrepareSuccess => Ok(row),
+            err => Err(err),
+        }
+    }
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+    pub(crate) fn fields(&self) -> Vec<Field> {
+        vec![
+            Field {
+                name: "id",
+                value: self.id.to_string(),
+                is_text: false,
+            },
+            Field {
+                name: "username",
+                value: self.username.clone(),
+                is_text: true,
+            },
+            Field {
+                name: "email",
+                value: self.email.clone(),
+                is_text: true,
+            },
+        ]
+    }
+}
+
+/// Serializes `source`, padding the unused end of its text fields with `fill`.
+pub(crate) fn serialize_row(source: &Row, destination: &mut [u8], fill: u8) {
+    unsafe {
+        ptr::copy_nonoverlapping(
+            &source.id as *const u32 as *const u8,
+            destination.as_mut_ptr().add(ID_OFFSET),
+            ID_SIZE,
+        );
+        let username_bytes =
+            truncate_to_char_boundary(&source.username, USERNAME_MAX_LENGTH).as_bytes();
+        let username_length = username_bytes.len();
+        ptr::copy_nonoverlapping(
+            username_bytes.as_ptr(),
+            destination.as_mut_ptr().add(USERNAME_OFFSET),
+            username_length,
+        );
+        ptr::write_bytes(
+            destination
+                .as_mut_ptr()
+                .add(USERNAME_OFFSET + username_length),
+            fill,
+            USERNAME_SIZE - username_length,
+        );
+        let email_bytes = truncate_to_char_boundary(&source.email, EMAIL_MAX_LENGTH).as_bytes();
+        let email_length = email_bytes.len();
+        ptr::copy_nonoverlapping(
+            email_bytes.as_ptr(),
+            destination.as_mut_ptr().add(EMAIL_OFFSET),
+            email_length,
+        );
+        ptr::write_bytes(
+            destination.as_mut_ptr().add(EMAIL_OFFSET + email_length),
+            fill,
+            EMAIL_SIZE - email_length,
+        );
+    }
+}
+
+/// Deserializes `source`, reading each text field up to the `fill` padding.
+pub(crate) fn deserialize_row(source: &[u8], destination: &mut Row, fill: u8) {
+    unsafe {
+        ptr::copy_nonoverlapping(
+            source.as_ptr().add(ID_OFFSET),
+            &mut destination.id as *mut u32 as *mut u8,
+            ID_SIZE,
+        );
+
+        let username_bytes = &source[USERNAME_OFFSET..USERNAME_OFFSET + USERNAME_SIZE];
+        destination.username = read_padded(username_bytes, fill);
+
+        let email_bytes = &source[EMAIL_OFFSET..EMAIL_OFFSET + EMAIL_SIZE];
+        destination.email = read_padded(email_bytes, fill);
+    }
+}
+
+/// Reads a text field up to its padding. A zero also ends it, so fields written with
+/// the default padding still read back under another fill byte.
+fn read_padded(field: &[u8], fill: u8) -> String {
+    String::from_utf8_lossy(padded_bytes(field, fill)).into_owned()
+}
+
+fn padded_bytes(field: &[u8], fill: u8) -> &[u8] {
+    let end = field
+        .iter()
+        .position(|&b| b == fill || b == 0)
+        .unwrap_or(field.len());
+    &field[..end]
+}
+
+/// A users row whose text fields are kept as the stored bytes, so values that aren't
+/// UTF-8 survive the round trip that `Row` would make lossy. Shown in hex.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RawRow {
+    id: u32,
+    username: Vec<u8>,
+    email: Vec<u8>,
+}
+
+impl RawRow {
+    /// Builds a raw row that fits the users layout. The bytes can't hold a zero or
+    /// `fill`, since either would end the field when it's read back.
+    pub(crate) fn from_parts(
+        id: u32,
+        username: impl Into<Vec<u8>>,
+        email: impl Into<Vec<u8>>,
+        fill: u8,
+    ) -> Result<RawRow, PrepareResult> {
+        let row = RawRow {
+            id,
+            username: username.into(),
+            email: email.into(),
+        };
+        if row.username.is_empty() || row.email.is_empty() {
+            return Err(PrepareResult::PrepareEmptyValue);
+        }
+        if row.username.len() > USERNAME_MAX_LENGTH || row.email.len() > EMAIL_MAX_LENGTH {
+            return Err(PrepareResult::PrepareStringTooLong);
+        }
+        if [&row.username, &row.email]
+            .iter()
+            .any(|value| value.iter().any(|&b| b == 0 || b == fill))
+        {
+            return Err(PrepareResult::PrepareSyntaxError);
+        }
+        Ok(row)
+    }
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    pub(crate) fn fields(&self) -> Vec<Field> {
+        vec![
+            Field {
+                name: "id",
+                value: self.id.to_string(),
+                is_text: false,
+            },
+            Field {
+                name: "username",
+                value: hex(&self.username),
+                is_text: true,
+            },
+            Field {
+                name: "email",
+                value: hex(&self.email),
+                is_text: true,
+            },
+        ]
+    }
+}
+
+pub(crate) fn serialize_raw_row(source: &RawRow, destination: &mut [u8], fill: u8) {
+    destination[ID_OFFSET..ID_OFFSET + ID_SIZE].copy_from_slice(&source.id.to_ne_bytes());
+    let fields = [
+        (USERNAME_OFFSET, USERNAME_SIZE, &source.username),
+        (EMAIL_OFFSET, EMAIL_SIZE, &source.email),
+    ];
+    for (offset, size, value) in fields {
+        let field = &mut destination[offset..offset + size];
+        field[..value.len()].copy_from_slice(value);
+        field[value.len()..].fill(fill);
+    }
+}
+
+pub(crate) fn deserialize_raw_row(source: &[u8], fill: u8) -> RawRow {
+    let id_bytes = &source[ID_OFFSET..ID_OFFSET + ID_SIZE];
+    let username = &source[USERNAME_OFFSET..USERNAME_OFFSET + USERNAME_SIZE];
+    let email = &source[EMAIL_OFFSET..EMAIL_OFFSET + EMAIL_SIZE];
+    RawRow {
+        id: u32::from_ne_bytes(id_bytes.try_into().unwrap()),
+        username: padded_bytes(username, fill).to_vec(),
+        email: padded_bytes(email, fill).to_vec(),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Reads back what `hex` writes, in either case. `None` for an odd length or a digit
+/// that isn't hex.
+pub(crate) fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deserialize_row, serialize_row, Row};
+    use crate::{ROW_SIZE, USERNAME_MAX_LENGTH, USERNAME_OFFSET};
+
+    #[test]
+    fn accessors_return_the_constructed_values() {
+        let row = Row::from_parts(7, "bala", "bala@gmail.com").unwrap();
+        assert_eq!(row.id(), 7);
+        assert_eq!(row.username(), "bala");
+        assert_eq!(row.email(), "bala@gmail.com");
+    }
+
+    #[test]
+    fn serialization_never_splits_a_multibyte_char() {
+        let row = Row {
+            id: 1,
+            username: format!("{}é", "a".repeat(USERNAME_MAX_LENGTH - 1)),
+            email: "a@x.com".to_string(),
+        };
+        let mut slot = [0u8; ROW_SIZE];
+        serialize_row(&row, &mut slot, 0);
+        let mut stored = Row::new();
+        deserialize_row(&slot, &mut stored, 0);
+        assert_eq!(stored.username(), "a".repeat(USERNAME_MAX_LENGTH - 1));
+    }
+
+    #[test]
+    fn a_non_zero_fill_byte_round_trips() {
+        let row = Row::from_parts(3, "bala", "bala@gmail.com").unwrap();
+        let mut slot = [0u8; ROW_SIZE];
+        serialize_row(&row, &mut slot, 0xff);
+        assert_eq!(slot[USERNAME_OFFSET + 4], 0xff);
+
+        let mut stored = Row::new();
+        deserialize_row(&slot, &mut stored, 0xff);
+        assert_eq!(stored, row);
+    }
+}
@@ -0,0 +1,149 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+fn run_repl(args: &[&str], input: &str) -> Output {
+    run_repl_in(Path::new("."), args, input)
+}
+
+fn run_repl_in(dir: &Path, args: &[&str], input: &str) -> Output {
+    run_repl_with_env(dir, args, &[], input)
+}
+
+fn run_repl_with_env(dir: &Path, args: &[&str], envs: &[(&str, &Path)], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_repl"))
+        .current_dir(dir)
+        .args(args)
+        .envs(envs.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    // The repl may exit before reading its input, closing the pipe under us.
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn failing_to_open_the_db_exits_nonzero() {
+    let status = run_repl(&["../outside"], ".exit\n").status;
+    assert!(!status.success());
+}
+
+#[test]
+fn exit_and_end_of_input_exit_zero() {
+    assert!(run_repl(&["cli_exit_code"], ".exit\n").status.success());
+    assert!(run_repl(&["cli_exit_code"], "").status.success());
+    std::fs::remove_file("db/cli_exit_code").unwrap();
+}
+
+#[test]
+fn exit_with_a_code_sets_the_exit_status() {
+    let status = |input| run_repl(&["cli_exit_status"], input).status.code();
+    assert_eq!(status(".exit 3\n"), Some(3));
+    assert_eq!(status(".exit nope\n"), Some(0));
+    std::fs::remove_file("db/cli_exit_status").unwrap();
+}
+
+#[test]
+fn readonly_rejects_inserts_but_allows_selects() {
+    run_repl(&["cli_readonly"], "insert 1 bala bala@gmail.com\n.exit\n");
+    let input = ".mode list\ninsert 2 anu anu@gmail.com\nselect\n.exit\n";
+    let output = run_repl(&["cli_readonly", "--readonly"], input);
+    std::fs::remove_file("db/cli_readonly").unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("ReadOnly: the table can't be changed"));
+    assert!(stdout.contains("1|bala|bala@gmail.com"));
+    assert!(!stdout.contains("anu"));
+}
+
+#[test]
+fn max_rows_caps_the_table() {
+    let input = "insert 1 bala bala@gmail.com\ninsert 2 anu anu@gmail.com\n.exit\n";
+    let output = run_repl(&["cli_max_rows", "--max-rows", "1", "--durable"], input);
+    std::fs::remove_file("db/cli_max_rows").unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Insert is not allowed, Table is full"));
+    assert!(!run_repl(&["cli_max_rows", "--max-rows", "x"], "")
+        .status
+        .success());
+}
+
+#[test]
+fn reopening_appends_after_the_existing_rows() {
+    let inserts = |ids: std::ops::Range<u32>| {
+        let mut input: String = ids
+            .map(|i| format!("insert {i} user{i} u{i}@x.com\n"))
+            .collect();
+        input.push_str(".exit\n");
+        input
+    };
+    // 20 rows spill into a second page, so the reopened table appends mid-page.
+    run_repl(&["cli_reopen"], &inserts(1..21));
+    run_repl(&["cli_reopen"], &inserts(21..26));
+    let output = run_repl(&["cli_reopen"], ".mode list\nselect\n.exit\n");
+    std::fs::remove_file("db/cli_reopen").unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let rows: Vec<&str> = stdout
+        .lines()
+        .map(|line| line.trim_start_matches("db -> "))
+        .filter(|line| line.contains('|'))
+        .collect();
+    let expected: Vec<String> = (1..26).map(|i| format!("{i}|user{i}|u{i}@x.com")).collect();
+    assert_eq!(rows, expected);
+}
+
+#[test]
+fn no_subdir_opens_the_path_verbatim() {
+    let dir = std::env::temp_dir().join(format!("try-db-no-subdir-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("verbatim.db");
+    let input = "insert 1 bala bala@gmail.com\n.exit\n";
+    let output = run_repl_in(&dir, &[path.to_str().unwrap(), "--no-subdir"], input);
+
+    let created = path.exists();
+    let subdir = dir.join("db").exists();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success());
+    assert!(created);
+    assert!(!subdir);
+}
+
+#[test]
+fn data_dir_env_var_replaces_the_db_directory() {
+    let dir = std::env::temp_dir().join(format!("try-db-data-dir-{}", std::process::id()));
+    let data_dir = dir.join("data");
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = "insert 1 bala bala@gmail.com\n.exit\n";
+    let envs = [("TRYDB_DATA_DIR", data_dir.as_path())];
+    let output = run_repl_with_env(&dir, &["cli_data_dir.db"], &envs, input);
+
+    let created = data_dir.join("cli_data_dir.db").exists();
+    let subdir = dir.join("db").exists();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success());
+    assert!(created);
+    assert!(!subdir);
+}
+
+#[test]
+fn json_mode_prints_one_object_per_result() {
+    let input = "insert 1 bala bala@gmail.com\nselect\nbogus\n.exit\n";
+    let output = run_repl(&["cli_json", "--json"], input);
+    std::fs::remove_file("db/cli_json").unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            r#"{"inserted": 1}"#,
+            r#"{"id": 1, "username": "bala", "email": "bala@gmail.com"}"#,
+            r#"{"error": "Unrecognized keyword at start of Some(\"bogus\")"}"#,
+        ]
+    );
+}